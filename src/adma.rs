@@ -0,0 +1,404 @@
+//! Advanced DMA (ADMA) descriptor tables and errors.
+//!
+//! The uSDHC supports two ADMA formats. ADMA1 describes 4 KiB pages with
+//! one-word descriptors. ADMA2 describes any word-aligned buffer with
+//! two-word descriptors.
+
+use crate::{ral, Usdhc};
+
+/// The most bytes transferred by a single ADMA2 descriptor.
+///
+/// The length field can express 65535 bytes, but descriptors need to
+/// keep the next address word aligned.
+const MAX_DESCRIPTOR_LENGTH: usize = 0xFFFC;
+
+/// An ADMA2 descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Adma2Descriptor {
+    /// Attributes in the low half word, length in the high half word.
+    attributes_length: u32,
+    /// System address of the data.
+    address: u32,
+}
+
+impl Adma2Descriptor {
+    /// Marks a valid descriptor.
+    const VALID: u32 = 1 << 0;
+    /// Marks the last descriptor in the table.
+    const END: u32 = 1 << 1;
+    /// Transfer data action.
+    const TRANSFER: u32 = 0b10 << 4;
+
+    /// An invalid descriptor, which stops the DMA.
+    const INVALID: Self = Self {
+        attributes_length: 0,
+        address: 0,
+    };
+
+    fn transfer(address: u32, length: usize) -> Self {
+        debug_assert!(length <= MAX_DESCRIPTOR_LENGTH);
+        Self {
+            attributes_length: (length as u32) << 16 | Self::TRANSFER | Self::VALID,
+            address,
+        }
+    }
+
+    /// Returns the number of bytes to transfer.
+    pub fn length(self) -> u32 {
+        self.attributes_length >> 16
+    }
+
+    /// Returns the address of the data.
+    pub fn address(self) -> u32 {
+        self.address
+    }
+
+    /// Returns `true` if the descriptor is valid.
+    pub fn is_valid(self) -> bool {
+        self.attributes_length & Self::VALID != 0
+    }
+
+    /// Returns `true` if this is the last descriptor in the table.
+    pub fn is_end(self) -> bool {
+        self.attributes_length & Self::END != 0
+    }
+}
+
+/// An error when creating a descriptor table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableError {
+    /// There's not enough descriptors in the table to describe all
+    /// of the buffers.
+    TooManyDescriptors,
+    /// A buffer isn't word aligned, or its length isn't a multiple of
+    /// four bytes.
+    Alignment,
+    /// There are no bytes to transfer.
+    Empty,
+}
+
+/// An ADMA2 descriptor table.
+///
+/// The table holds up to `N` descriptors. Every buffer requires at least
+/// one descriptor, and buffers larger than 65532 bytes need more than one
+/// descriptor.
+///
+/// The DMA reads the table from memory. Place the table in memory that's
+/// accessible by the uSDHC DMA, then register it with
+/// [`set_adma2_table`](crate::Usdhc::set_adma2_table).
+///
+/// ```no_run
+/// use imxrt_usdhc::{Adma2Table, DmaSelect, Usdhc};
+///
+/// static mut TABLE: Adma2Table<8> = Adma2Table::new();
+///
+/// # let mut usdhc = unsafe { Usdhc::new(core::ptr::null(), 198_000_000) };
+/// // Safety: the table is only used by this driver.
+/// usdhc.set_adma2_table(unsafe { &mut *core::ptr::addr_of_mut!(TABLE) });
+/// usdhc.set_dma_enable(Some(DmaSelect::Adma2));
+/// ```
+#[derive(Debug)]
+#[repr(C, align(4))]
+pub struct Adma2Table<const N: usize> {
+    descriptors: [Adma2Descriptor; N],
+}
+
+impl<const N: usize> Adma2Table<N> {
+    /// Create a table of invalid descriptors.
+    pub const fn new() -> Self {
+        Self {
+            descriptors: [Adma2Descriptor::INVALID; N],
+        }
+    }
+
+    /// Describe the buffers, in order.
+    ///
+    /// On success, the table is ready for a DMA transfer, and this returns
+    /// the number of descriptors in use.
+    pub fn describe<'a>(
+        &mut self,
+        buffers: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<usize, TableError> {
+        let segments = buffers
+            .into_iter()
+            .map(|buffer| (buffer.as_ptr() as usize, buffer.len()));
+        fill_adma2(&mut self.descriptors, segments)
+    }
+
+    /// Returns the descriptors in the table.
+    pub fn descriptors(&self) -> &[Adma2Descriptor] {
+        &self.descriptors
+    }
+}
+
+impl<const N: usize> Default for Adma2Table<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fill the descriptors so that they describe the segments, in order.
+///
+/// Each segment is a buffer address and length. Returns the number of
+/// descriptors in use.
+pub(crate) fn fill_adma2(
+    descriptors: &mut [Adma2Descriptor],
+    segments: impl Iterator<Item = (usize, usize)>,
+) -> Result<usize, TableError> {
+    let mut used = 0;
+    for (mut address, mut length) in segments {
+        if address % 4 != 0 || length % 4 != 0 {
+            return Err(TableError::Alignment);
+        }
+        while length > 0 {
+            let descriptor = descriptors
+                .get_mut(used)
+                .ok_or(TableError::TooManyDescriptors)?;
+            let chunk = length.min(MAX_DESCRIPTOR_LENGTH);
+            *descriptor = Adma2Descriptor::transfer(address as u32, chunk);
+            used += 1;
+            address += chunk;
+            length -= chunk;
+        }
+    }
+
+    let last = used.checked_sub(1).ok_or(TableError::Empty)?;
+    descriptors[last].attributes_length |= Adma2Descriptor::END;
+    Ok(used)
+}
+
+/// The action of an ADMA1 descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Adma1Action {
+    /// No operation; go to the next descriptor.
+    Nop = 0b00,
+    /// Set the length for the next transfer.
+    Set = 0b01,
+    /// Transfer data at the page address.
+    Transfer = 0b10,
+    /// Continue with the descriptor at the page address.
+    Link = 0b11,
+}
+
+/// An ADMA1 descriptor.
+///
+/// Every descriptor is one word. The upper bits are either a page
+/// address or a length. The lower bits are attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Adma1Descriptor(u32);
+
+impl Adma1Descriptor {
+    const VALID: u32 = 1 << 0;
+    const END: u32 = 1 << 1;
+    const ACTION_OFFSET: u32 = 4;
+    const VALUE_OFFSET: u32 = 12;
+
+    /// An invalid descriptor, which stops the DMA.
+    const INVALID: Self = Self(0);
+
+    fn new(action: Adma1Action, value: u32) -> Self {
+        Self(value << Self::VALUE_OFFSET | (action as u32) << Self::ACTION_OFFSET | Self::VALID)
+    }
+
+    /// Returns the descriptor's action.
+    pub fn action(self) -> Adma1Action {
+        match self.0 >> Self::ACTION_OFFSET & 0b11 {
+            0b00 => Adma1Action::Nop,
+            0b01 => Adma1Action::Set,
+            0b10 => Adma1Action::Transfer,
+            _ => Adma1Action::Link,
+        }
+    }
+
+    /// Returns the descriptor's address or length.
+    ///
+    /// For a [`Set`](Adma1Action::Set) action, this is the length in bytes. Otherwise,
+    /// this is an address.
+    pub fn value(self) -> u32 {
+        match self.action() {
+            Adma1Action::Set => self.0 >> Self::VALUE_OFFSET & 0xFFFF,
+            _ => self.0 & !((1 << Self::VALUE_OFFSET) - 1),
+        }
+    }
+
+    /// Returns `true` if the descriptor is valid.
+    pub fn is_valid(self) -> bool {
+        self.0 & Self::VALID != 0
+    }
+
+    /// Returns `true` if this is the last descriptor in the table.
+    pub fn is_end(self) -> bool {
+        self.0 & Self::END != 0
+    }
+}
+
+/// The size of an ADMA1 page, in bytes.
+const ADMA1_PAGE: usize = 4096;
+
+/// The most bytes transferred by a single ADMA1 transfer descriptor.
+///
+/// The length field can express 65535 bytes, but the next transfer needs to
+/// start on a page.
+const MAX_ADMA1_LENGTH: usize = 0xF000;
+
+/// An ADMA1 descriptor table.
+///
+/// The table holds up to `N` descriptors. Every buffer requires at least
+/// two descriptors: one to set the length, and another to transfer the data.
+/// Buffers larger than 60 KiB need more descriptors.
+///
+/// ADMA1 transfers data in pages. Every buffer must start on a 4 KiB boundary,
+/// and each buffer's length must be a multiple of four bytes.
+///
+/// The DMA reads the table from memory. Place the table in memory that's
+/// accessible by the uSDHC DMA, then register it with
+/// [`set_adma1_table`](crate::Usdhc::set_adma1_table).
+#[derive(Debug)]
+#[repr(C, align(4))]
+pub struct Adma1Table<const N: usize> {
+    descriptors: [Adma1Descriptor; N],
+}
+
+impl<const N: usize> Adma1Table<N> {
+    /// Create a table of invalid descriptors.
+    pub const fn new() -> Self {
+        Self {
+            descriptors: [Adma1Descriptor::INVALID; N],
+        }
+    }
+
+    /// Describe the buffers, in order.
+    ///
+    /// On success, the table is ready for a DMA transfer, and this returns
+    /// the number of descriptors in use.
+    pub fn describe<'a>(
+        &mut self,
+        buffers: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<usize, TableError> {
+        let segments = buffers
+            .into_iter()
+            .map(|buffer| (buffer.as_ptr() as usize, buffer.len()));
+        fill_adma1(&mut self.descriptors, segments)
+    }
+
+    /// Returns the descriptors in the table.
+    pub fn descriptors(&self) -> &[Adma1Descriptor] {
+        &self.descriptors
+    }
+}
+
+impl<const N: usize> Default for Adma1Table<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fill the descriptors so that they describe the segments, in order.
+///
+/// Each segment is a buffer address and length. Returns the number of
+/// descriptors in use.
+pub(crate) fn fill_adma1(
+    descriptors: &mut [Adma1Descriptor],
+    segments: impl Iterator<Item = (usize, usize)>,
+) -> Result<usize, TableError> {
+    let mut used = 0;
+    for (mut address, mut length) in segments {
+        if address % ADMA1_PAGE != 0 || length % 4 != 0 {
+            return Err(TableError::Alignment);
+        }
+        while length > 0 {
+            let [set, transfer] = descriptors
+                .get_mut(used..used + 2)
+                .and_then(|pair| <&mut [_; 2]>::try_from(pair).ok())
+                .ok_or(TableError::TooManyDescriptors)?;
+            let chunk = length.min(MAX_ADMA1_LENGTH);
+            *set = Adma1Descriptor::new(Adma1Action::Set, chunk as u32);
+            *transfer = Adma1Descriptor::new(
+                Adma1Action::Transfer,
+                (address >> Adma1Descriptor::VALUE_OFFSET) as u32,
+            );
+            used += 2;
+            address += chunk;
+            length -= chunk;
+        }
+    }
+
+    let last = used.checked_sub(1).ok_or(TableError::Empty)?;
+    descriptors[last].0 |= Adma1Descriptor::END;
+    Ok(used)
+}
+
+/// The state of the ADMA when it signaled an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum AdmaState {
+    /// Stopped the DMA.
+    Stop = 0,
+    /// Fetching a descriptor.
+    FetchDescriptor = 1,
+    /// Changing the address. This state is never used.
+    ChangeAddress = 2,
+    /// Transferring data.
+    Transfer = 3,
+}
+
+/// Describes an ADMA error.
+///
+/// Use [`adma_error`](crate::Usdhc::adma_error) to understand why an
+/// ADMA transfer signaled [`Status::DMAE`](crate::Status::DMAE).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmaError {
+    /// The state of the ADMA when it failed.
+    pub state: AdmaState,
+    /// The transfer's length did not match the length of the
+    /// descriptors.
+    pub length_mismatch: bool,
+    /// The ADMA fetched an invalid descriptor.
+    pub descriptor_error: bool,
+    /// The ADMA system address when the error occurred.
+    ///
+    /// When the state is [`AdmaState::Stop`], this points to the next descriptor.
+    /// Otherwise, this points to the descriptor that caused the error.
+    pub address: u32,
+}
+
+impl Usdhc {
+    /// Use this table for ADMA1 transfers.
+    ///
+    /// The driver keeps the table for all ADMA1 transfers. It replaces any
+    /// existing table.
+    pub fn set_adma1_table<const N: usize>(&mut self, table: &'static mut Adma1Table<N>) {
+        self.adma1 = Some(&mut table.descriptors);
+    }
+
+    /// Use this table for ADMA2 transfers.
+    ///
+    /// The driver keeps the table for all ADMA2 transfers. It replaces any
+    /// existing table.
+    pub fn set_adma2_table<const N: usize>(&mut self, table: &'static mut Adma2Table<N>) {
+        self.adma2 = Some(&mut table.descriptors);
+    }
+
+    /// Returns the ADMA error, if any.
+    ///
+    /// This returns `None` if there's no DMA error flagged in the status.
+    pub fn adma_error(&self) -> Option<AdmaError> {
+        if !self.status().intersects(crate::Status::DMAE) {
+            return None;
+        }
+
+        let (state, length_mismatch, descriptor_error) =
+            ral::read_reg!(ral, self.inst, ADMA_ERR_STATUS, ADMAES, ADMALME, ADMADCE);
+        Some(AdmaError {
+            // Safety: ADMAES is two bits, and all four values are represented
+            // in the enum.
+            state: unsafe { core::mem::transmute::<u32, AdmaState>(state) },
+            length_mismatch: length_mismatch != 0,
+            descriptor_error: descriptor_error != 0,
+            address: ral::read_reg!(ral, self.inst, ADMA_SYS_ADDR),
+        })
+    }
+}
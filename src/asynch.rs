@@ -0,0 +1,201 @@
+//! An async transport, driven by the uSDHC interrupt.
+
+use core::{future::Future, task::Poll};
+
+use sdio_host::{
+    common_cmd::Resp, sd::BusWidth, BlockingSdioTransport, Cmd, TransportData, TransportMode,
+};
+
+use crate::{ral, AtomicWaker, CardError, TransferState, TransportError, Usdhc};
+
+/// An async SDIO transport using uSDHC.
+///
+/// The transport starts each transfer, then waits for the uSDHC interrupt.
+/// Call [`on_interrupt`](Self::on_interrupt) from your uSDHC interrupt handler
+/// to wake the transport. The transport doesn't depend on an executor.
+///
+/// ```no_run
+/// use imxrt_usdhc::{AsyncUsdhc, AtomicWaker, Usdhc};
+///
+/// static WAKER: AtomicWaker = AtomicWaker::new();
+/// const USDHC1: *const () = 0x402C_0000 as _;
+///
+/// # async fn run() {
+/// let usdhc = unsafe { Usdhc::new(USDHC1, 198_000_000) };
+/// let mut usdhc = AsyncUsdhc::new(usdhc, &WAKER);
+/// let mut delay = |_ms: u32| async {};
+/// usdhc.power_cycle(&mut delay).await.unwrap();
+/// # }
+///
+/// // In your uSDHC interrupt handler:
+/// unsafe { AsyncUsdhc::on_interrupt(USDHC1, &WAKER) };
+/// ```
+///
+/// # Interrupts
+///
+/// The interrupt handler masks the uSDHC interrupts that are pending, then
+/// wakes the transport. The transport handles its status flags, and re-enables
+/// its interrupts, when it runs again. If you route other status flags to
+/// the interrupt, handle those flags and re-enable their interrupts yourself.
+/// You're responsible for unmasking the uSDHC interrupt in your interrupt
+/// controller.
+///
+/// # Cancellation
+///
+/// If you drop a `transfer` future before it completes, the transport aborts
+/// the transfer. Don't leak the future; see [`transfer`](Self::transfer) for
+/// more information.
+///
+/// Otherwise, the transport behaves like the [`BlockingSdioTransport`]
+/// implementation on [`Usdhc`].
+pub struct AsyncUsdhc {
+    usdhc: Usdhc,
+    waker: &'static AtomicWaker,
+}
+
+/// Aborts the transfer if the transfer future drops before it completes.
+struct Active<'a>(&'a mut Usdhc);
+
+impl Drop for Active<'_> {
+    fn drop(&mut self) {
+        if self.0.transfer_state() != TransferState::Idle {
+            let _ = self.0.abort_transfer();
+        }
+    }
+}
+
+impl AsyncUsdhc {
+    /// Create an async transport.
+    ///
+    /// Use a different `waker` for each uSDHC instance.
+    pub fn new(usdhc: Usdhc, waker: &'static AtomicWaker) -> Self {
+        Self { usdhc, waker }
+    }
+
+    /// Wake the async transport.
+    ///
+    /// Call this from your uSDHC interrupt handler. See the
+    /// [interrupt documentation](Self#interrupts) for more information.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be the pointer to the uSDHC peripheral used by the async
+    /// transport that registered with `waker`.
+    pub unsafe fn on_interrupt(ptr: *const (), waker: &AtomicWaker) {
+        let inst = unsafe { ral::Instance::new(ptr) };
+        let pending = ral::read_reg!(ral, inst, INT_STATUS);
+        let signals = ral::read_reg!(ral, inst, INT_SIGNAL_EN);
+        ral::write_reg!(ral, inst, INT_SIGNAL_EN, signals & !pending);
+        waker.wake();
+    }
+
+    /// Access the uSDHC driver.
+    ///
+    /// Use this for operations that don't need the interrupt, like selecting
+    /// DMA or setting a [`BusMode`](crate::BusMode).
+    ///
+    /// If a transfer is still active, this aborts the transfer. That only
+    /// happens if a `transfer` future was leaked.
+    pub fn usdhc(&mut self) -> &mut Usdhc {
+        self.abort_transfer();
+        &mut self.usdhc
+    }
+
+    /// Release the uSDHC driver.
+    ///
+    /// Like [`usdhc`](Self::usdhc), this aborts any active transfer.
+    pub fn release(mut self) -> Usdhc {
+        self.abort_transfer();
+        self.usdhc
+    }
+
+    /// Abort the active transfer, if there is one.
+    ///
+    /// Once this returns, the peripheral no longer accesses a transfer buffer.
+    fn abort_transfer(&mut self) {
+        if self.usdhc.transfer_state() != TransferState::Idle {
+            let _ = self.usdhc.abort_transfer();
+        }
+    }
+
+    /// Send a command, and transfer its data.
+    ///
+    /// This behaves like the blocking `transfer`, but it waits for the
+    /// uSDHC interrupt instead of polling. With [card detection](Usdhc::set_card_detect),
+    /// a missing card is [`CardError::NoCard`].
+    ///
+    /// # Safety
+    ///
+    /// The peripheral accesses the buffer in `data` while the transfer is
+    /// active. Either poll the future to completion, or drop it; dropping the
+    /// future aborts the transfer. Don't leak the future, for instance with
+    /// [`forget`](core::mem::forget). If you leak the future before it
+    /// completes, the peripheral may access the buffer after the borrow ends.
+    pub async unsafe fn transfer<R: Resp>(
+        &mut self,
+        command: &Cmd<R>,
+        response: &mut [u32; 4],
+        data: TransportData<'_>,
+    ) -> Result<(), CardError> {
+        // Safety: the data borrow lives as long as this future. If the future
+        // drops before the transfer finishes, Active aborts the transfer. The
+        // caller promises not to leak the future.
+        if let Err(error) = unsafe { self.usdhc.start_transfer(command, data) } {
+            return Err(self.usdhc.card_error(error));
+        }
+        let active = Active(&mut self.usdhc);
+        let waker = self.waker;
+
+        core::future::poll_fn(|cx| {
+            waker.register(cx.waker());
+            match active.0.on_interrupt() {
+                TransferState::Complete | TransferState::Error(_) => {
+                    Poll::Ready(active.0.transfer_result())
+                }
+                _ => {
+                    active.0.arm_transfer_interrupts();
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+        .unwrap_or(Err(TransportError::uncategorized()))
+        .map(|result| *response = result)
+        .map_err(|error| active.0.card_error(error))
+    }
+
+    /// Power cycle the card, then prepare to identify the card.
+    ///
+    /// This follows the blocking power cycle, but it awaits each wait.
+    /// `delay` returns a future that completes after the given number
+    /// of milliseconds. Like [`transfer`](Self::transfer), a missing card
+    /// is [`CardError::NoCard`].
+    pub async fn power_cycle<F: Future<Output = ()>>(
+        &mut self,
+        delay: &mut impl FnMut(u32) -> F,
+    ) -> Result<(), CardError> {
+        let mut step = 0;
+        loop {
+            match self.usdhc.power_cycle_step(step) {
+                Ok(Some(ms)) => delay(ms).await,
+                Ok(None) => return Ok(()),
+                Err(error) => return Err(self.usdhc.card_error(error)),
+            }
+            step += 1;
+        }
+    }
+
+    /// Set the bus width.
+    ///
+    /// This only updates the peripheral; it never waits.
+    pub async fn set_bus_width(&mut self, bus_width: BusWidth) -> Result<(), TransportError> {
+        self.usdhc.set_bus_width(bus_width)
+    }
+
+    /// Set the SD default or high speed mode.
+    ///
+    /// Changing the clock briefly polls for the clock to stabilize.
+    pub async fn set_mode(&mut self, mode: TransportMode) -> Result<(), TransportError> {
+        self.usdhc.set_mode(mode)
+    }
+}
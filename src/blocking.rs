@@ -7,12 +7,49 @@ use sdio_host::{
 };
 pub use sdio_host::{HostError, TransportError};
 
-use crate::{ral, DataTransferWidth, PresentState, Status, Usdhc, Watermark};
+use crate::{
+    adma::{fill_adma1, fill_adma2},
+    ral, AutoCmd12Error, BusMode, DataTransferWidth, DmaSelect, ModeError, MultiBlockMode, NoCard,
+    PresentState, SetBlockCount, Status, TableError, TimeoutError, TuningError, Usdhc, Watermark,
+};
 
 /// A blocking SDIO host using uSDHC.
 pub type BlockingSdioHost = sdio_host::BlockingSdioHost<Usdhc>;
 
-fn transport_error(status: Status) -> TransportError {
+/// The block size for multi-block transfers.
+const BLOCK_SIZE: usize = 512;
+
+/// CMD12, STOP_TRANSMISSION.
+const STOP_TRANSMISSION: u8 = 12;
+/// CMD17, READ_SINGLE_BLOCK.
+const READ_SINGLE_BLOCK: u8 = 17;
+/// CMD18, READ_MULTIPLE_BLOCK.
+const READ_MULTIPLE_BLOCK: u8 = 18;
+/// CMD24, WRITE_BLOCK.
+const WRITE_BLOCK: u8 = 24;
+/// CMD25, WRITE_MULTIPLE_BLOCK.
+const WRITE_MULTIPLE_BLOCK: u8 = 25;
+
+/// Returns `true` if the command's response includes a busy signal on DAT0.
+///
+/// These are the R1b and R5b responses. As of this writing, sdio-host doesn't
+/// describe busy responses, so this is derived from the command index. `data`
+/// is `true` if the command has a data phase.
+///
+/// Only a command with a 48-bit response can signal busy. For instance, CMD7
+/// without a response deselects the card, and the card never signals busy.
+fn signals_busy(index: u8, data: bool) -> bool {
+    match index {
+        // SELECT_CARD, STOP_TRANSMISSION, SET_WRITE_PROT, CLR_WRITE_PROT, ERASE.
+        7 | 12 | 28 | 29 | 38 => true,
+        // eMMC SWITCH has no data phase. SD SWITCH_FUNC has a data phase, and
+        // no busy signal.
+        6 => !data,
+        _ => false,
+    }
+}
+
+pub(crate) fn transport_error(status: Status, auto_cmd12: AutoCmd12Error) -> TransportError {
     if status.intersects(Status::CTOE) {
         TransportError::CommandTimeout
     } else if status.intersects(Status::CIE) {
@@ -23,117 +60,412 @@ fn transport_error(status: Status) -> TransportError {
         TransportError::Crc
     } else if status.intersects(Status::CEBE | Status::DEBE) {
         TransportError::Bit
+    } else if status.intersects(Status::AC12E) {
+        auto_cmd12_transport_error(auto_cmd12)
     } else {
         TransportError::uncategorized()
     }
 }
 
+fn auto_cmd12_transport_error(auto_cmd12: AutoCmd12Error) -> TransportError {
+    if auto_cmd12.intersects(AutoCmd12Error::AC12TOE) {
+        TransportError::CommandTimeout
+    } else if auto_cmd12.intersects(AutoCmd12Error::AC12IE) {
+        TransportError::CommandIndex
+    } else if auto_cmd12.intersects(AutoCmd12Error::AC12CE) {
+        TransportError::Crc
+    } else if auto_cmd12.intersects(AutoCmd12Error::AC12EBE) {
+        TransportError::Bit
+    } else {
+        TransportError::uncategorized()
+    }
+}
+
+/// Command waits become command timeouts, and data waits become data timeouts.
+impl From<TimeoutError> for TransportError {
+    fn from(timeout: TimeoutError) -> Self {
+        match timeout {
+            TimeoutError::Inhibit(state) if state.intersects(PresentState::CIHB) => {
+                TransportError::CommandTimeout
+            }
+            TimeoutError::Status(flags) if flags.intersects(Status::CC) => {
+                TransportError::CommandTimeout
+            }
+            TimeoutError::Inhibit(_) | TimeoutError::Status(_) => TransportError::DataTimeout,
+            _ => TransportError::uncategorized(),
+        }
+    }
+}
+
+/// Failing to re-tune means that the sampling clock is unreliable.
+impl From<TuningError> for TransportError {
+    fn from(error: TuningError) -> Self {
+        match error {
+            TuningError::Transport(error) => error,
+            _ => TransportError::Crc,
+        }
+    }
+}
+
+impl From<ModeError> for TransportError {
+    fn from(error: ModeError) -> Self {
+        match error {
+            ModeError::ClockUnreachable | ModeError::BusWidth | ModeError::NotSupported => {
+                TransportError::NotSupported
+            }
+            ModeError::Timeout(timeout) => timeout.into(),
+        }
+    }
+}
+
+/// Buffers for a scatter-gather transfer.
+///
+/// See [`transfer_vectored`](Usdhc::transfer_vectored) for more information.
+#[derive(Debug)]
+pub enum VectoredData<'a, 'b> {
+    /// Read from the card into these buffers, in order.
+    Read(&'a mut [&'b mut [u8]]),
+    /// Write these buffers to the card, in order.
+    Write(&'a [&'b [u8]]),
+}
+
+/// An error from a scatter-gather transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectoredError {
+    /// The host doesn't support ADMA.
+    NotSupported,
+    /// ADMA isn't selected, or there's no table for the selected ADMA.
+    NoTable,
+    /// The table can't describe the buffers.
+    Table(TableError),
+    /// The transfer failed.
+    Transport(TransportError),
+}
+
+impl From<TableError> for VectoredError {
+    fn from(error: TableError) -> Self {
+        Self::Table(error)
+    }
+}
+
+impl From<TransportError> for VectoredError {
+    fn from(error: TransportError) -> Self {
+        Self::Transport(error)
+    }
+}
+
+impl From<TimeoutError> for VectoredError {
+    fn from(timeout: TimeoutError) -> Self {
+        Self::Transport(timeout.into())
+    }
+}
+
+impl From<VectoredError> for TransportError {
+    fn from(error: VectoredError) -> Self {
+        match error {
+            VectoredError::Transport(error) => error,
+            _ => TransportError::NotSupported,
+        }
+    }
+}
+
+/// How a data phase moves data.
+pub(crate) enum DataPath<'a> {
+    /// There's no data phase.
+    None,
+    /// The CPU copies data through the data buffer.
+    Cpu(TransportData<'a>),
+    /// The internal DMA moves data. The DMA is ready to go.
+    Dma(DmaSelect),
+}
+
 impl Usdhc {
-    fn wait_for(&mut self, flags: Status) -> Result<(), TransportError> {
-        while {
-            let status = self.status();
-            if status.is_error() {
-                return Err(transport_error(status));
-            }
-            !status.intersects(flags)
-        } {}
-        self.clear_status(flags);
+    /// Transfer data between the card and multiple buffers.
+    ///
+    /// This uses ADMA to gather the buffers into one transfer. Before using this
+    /// method, register a table with [`set_adma1_table`](Self::set_adma1_table) or
+    /// [`set_adma2_table`](Self::set_adma2_table), and select the matching ADMA
+    /// with [`set_dma_enable`](Self::set_dma_enable). See the table documentation
+    /// for buffer alignment requirements.
+    ///
+    /// Returns an error if ADMA isn't ready, or if the table can't describe the
+    /// buffers. If the transfer fails due to a DMA error, the transport error is
+    /// uncategorized; use [`adma_error`](Self::adma_error) to understand the failure.
+    pub fn transfer_vectored<R: Resp>(
+        &mut self,
+        command: &Cmd<R>,
+        response: &mut [u32; 4],
+        data: VectoredData<'_, '_>,
+    ) -> Result<(), VectoredError> {
+        self.check_card().map_err(TransportError::from)?;
+        self.retune_if_needed().map_err(TransportError::from)?;
+        self.prepare_command()?;
+
+        let (length, read) = match &data {
+            VectoredData::Read(buffers) => (buffers.iter().map(|b| b.len()).sum(), true),
+            VectoredData::Write(buffers) => (buffers.iter().map(|b| b.len()).sum(), false),
+        };
+
+        let dma = match data {
+            VectoredData::Read(buffers) => {
+                self.prepare_adma(buffers.iter().map(|b| (b.as_ptr() as usize, b.len())))
+            }
+            VectoredData::Write(buffers) => {
+                self.prepare_adma(buffers.iter().map(|b| (b.as_ptr() as usize, b.len())))
+            }
+        }?;
+
+        self.execute(command, response, length, read, DataPath::Dma(dma))?;
         Ok(())
     }
 
-    /// Correct execution depends on watermark levels. See the `transfer`
-    /// implementation for more details.
-    fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), TransportError> {
-        for bytes in buffer.chunks_exact_mut(4) {
-            self.wait_for(Status::BRR)?;
+    /// Prepare the selected ADMA to move the segments.
+    fn prepare_adma(
+        &mut self,
+        segments: impl Iterator<Item = (usize, usize)>,
+    ) -> Result<DmaSelect, VectoredError> {
+        if !self.capabilities().adma {
+            return Err(VectoredError::NotSupported);
+        }
+        let dma = self.dma.ok_or(VectoredError::NoTable)?;
+        let address = match dma {
+            DmaSelect::Adma1 => {
+                let table = self.adma1.as_deref_mut().ok_or(VectoredError::NoTable)?;
+                fill_adma1(table, segments)?;
+                table.as_ptr() as u32
+            }
+            DmaSelect::Adma2 => {
+                let table = self.adma2.as_deref_mut().ok_or(VectoredError::NoTable)?;
+                fill_adma2(table, segments)?;
+                table.as_ptr() as u32
+            }
+            DmaSelect::Simple => return Err(VectoredError::NoTable),
+        };
+        ral::write_reg!(ral, self.inst, ADMA_SYS_ADDR, address);
+        Ok(dma)
+    }
 
-            let word = self.read_data_buffer();
-            bytes.copy_from_slice(&word.to_le_bytes());
+    /// Wait for the command and data lines to be free, then clear
+    /// all status for the next command.
+    pub(crate) fn prepare_command(&mut self) -> Result<(), TimeoutError> {
+        if self.status().is_error() {
+            self.clear_status(Status::ERRORS);
         }
 
+        let inhibit = PresentState::CIHB | PresentState::CDIHB;
+        self.poll(|usdhc| (!usdhc.present_state().intersects(inhibit)).then_some(()))
+            .ok_or_else(|| TimeoutError::Inhibit(self.present_state() & inhibit))?;
+
+        self.clear_status(Status::all());
         Ok(())
     }
 
-    /// Correct execution depends on watermark levels. See the `transfer`
-    /// implementation for more details.
-    fn write_from(&mut self, buffer: &[u8]) -> Result<(), TransportError> {
-        for bytes in buffer.chunks_exact(4) {
-            self.wait_for(Status::BWR)?;
+    /// Prepare the DMA to move the data for the command.
+    ///
+    /// Returns `None` if the DMA can't move this data. In that case, the
+    /// CPU needs to move the data.
+    pub(crate) fn prepare_dma(
+        &mut self,
+        command: u8,
+        data: &TransportData<'_>,
+    ) -> Option<DmaSelect> {
+        let segment = match data {
+            TransportData::Read { buffer } => (buffer.as_ptr() as usize, buffer.len()),
+            TransportData::Write { buffer } => (buffer.as_ptr() as usize, buffer.len()),
+            TransportData::None => return None,
+        };
 
-            let word = u32::from_le_bytes(bytes.try_into().unwrap());
-            self.write_data_buffer(word);
+        let capabilities = self.capabilities();
+        match self.dma? {
+            // Fall back to the CPU if the host can't move the data.
+            DmaSelect::Simple if !capabilities.dma => None,
+            // The CMD23 flags need the simple DMA address register.
+            DmaSelect::Simple if self.auto_cmd23_flags(command).is_some() => None,
+            // The DMA needs a word-aligned buffer.
+            DmaSelect::Simple if segment.0 % 4 == 0 => {
+                ral::write_reg!(ral, self.inst, DS_ADDR, segment.0 as u32);
+                Some(DmaSelect::Simple)
+            }
+            DmaSelect::Simple => None,
+            DmaSelect::Adma1 | DmaSelect::Adma2 => {
+                self.prepare_adma(core::iter::once(segment)).ok()
+            }
         }
+    }
 
-        Ok(())
+    /// Send the command, then move data along the path.
+    ///
+    /// `length` is the number of bytes in the data phase. `read` is `true`
+    /// if the data phase reads from the card.
+    fn execute<R: Resp>(
+        &mut self,
+        command: &Cmd<R>,
+        response: &mut [u32; 4],
+        length: usize,
+        read: bool,
+        path: DataPath<'_>,
+    ) -> Result<(), TransportError> {
+        let busy = self.issue(command, length, read, &path)?;
+        let result = self.complete(command, response, busy, path);
+        self.resume_card_detect();
+        result
     }
-}
 
-/// Implements the blocking SDIO transport.
-///
-/// # Assumptions
-///
-/// Power cycle assumes that your reset line controls the hardware. If this isn't
-/// the case, the implementation may not actually power cycle your device. You're
-/// responsible for muxing the reset pin with your IOMUXC peripheral.
-impl BlockingSdioTransport for Usdhc {
-    fn transfer<R>(
+    /// Wait for the command response, then move data along the path.
+    fn complete<R: Resp>(
         &mut self,
         command: &Cmd<R>,
         response: &mut [u32; 4],
-        data: TransportData<'_>,
-    ) -> Result<(), TransportError>
-    where
-        R: Resp,
-    {
-        if self.status().is_error() {
-            self.clear_status(Status::ERRORS);
+        busy: bool,
+        path: DataPath<'_>,
+    ) -> Result<(), TransportError> {
+        self.wait_for(Status::CC)?;
+        self.read_response(command.response_len(), response);
+
+        // Once the CPU moves all data, wait for the transfer to complete.
+        // This includes any automatic CMD12.
+        match path {
+            // The peripheral signals transfer complete once the card releases
+            // DAT0. If the card stays busy, the data timeout bounds the wait.
+            DataPath::None if busy => self.wait_for(Status::TC)?,
+            DataPath::None | DataPath::Cpu(TransportData::None) => {}
+            DataPath::Cpu(TransportData::Read { buffer }) => {
+                self.read_into(buffer)?;
+                self.wait_for(Status::TC)?;
+            }
+            DataPath::Cpu(TransportData::Write { buffer }) => {
+                self.write_from(buffer)?;
+                self.wait_for(Status::TC)?;
+            }
+            DataPath::Dma(DmaSelect::Simple) => self.wait_for_simple_dma()?,
+            DataPath::Dma(_) => self.wait_for(Status::TC)?,
         }
 
-        while self
-            .present_state()
-            .intersects(PresentState::CIHB | PresentState::CDIHB)
-        {
-            // TODO timeout in case these never clear...
+        Ok(())
+    }
+
+    /// Configure the data phase, then send the command.
+    ///
+    /// Returns `true` if the card signals busy after the response. This
+    /// doesn't wait for anything.
+    pub(crate) fn issue<R: Resp>(
+        &mut self,
+        command: &Cmd<R>,
+        length: usize,
+        read: bool,
+        path: &DataPath<'_>,
+    ) -> Result<bool, TransportError> {
+        if let DataPath::Dma(_) = path {
+            let words = (length / 4).clamp(1, 16) as u8;
+            self.set_watermark(Watermark {
+                write_level: words,
+                read_level: words,
+            });
+        } else {
+            self.set_watermark(Watermark {
+                write_level: 128,
+                read_level: 1,
+            });
         }
 
-        self.clear_status(Status::all());
+        let data = !matches!(path, DataPath::None);
+        let (rsptyp, busy) = match command.response_len() {
+            ResponseLen::Zero => (0, false),
+            ResponseLen::R136 => (1, false),
+            ResponseLen::R48 if signals_busy(command.cmd, data) => (3, true),
+            ResponseLen::R48 => (2, false),
+        };
 
-        // For now, always signal whenever one data word is available for
-        // reading. I'm not sure what happens if the data is not a multiple
-        // of four bytes...
-        assert!(
-            data.len() % 4 == 0,
-            "Data lenght must be a multiple of four"
-        );
-        self.set_watermark(Watermark {
-            write_level: 128,
-            read_level: 1,
-        });
+        // The controller counts blocks for multi-block transfers. It either
+        // stops the transfer with an automatic CMD12, or defines the
+        // transfer with an automatic CMD23.
+        let multi_block = matches!(command.cmd, READ_MULTIPLE_BLOCK | WRITE_MULTIPLE_BLOCK);
+        let (block_size, block_count) = if multi_block {
+            if length % BLOCK_SIZE != 0 || length / BLOCK_SIZE > u16::MAX as usize {
+                return Err(TransportError::NotSupported);
+            }
+            (BLOCK_SIZE, length / BLOCK_SIZE)
+        } else {
+            (length, 1)
+        };
+        if block_size > self.capabilities().max_block_length {
+            return Err(TransportError::NotSupported);
+        }
+
+        // DDR modes fix the block length for block reads and writes.
+        let single_block = matches!(command.cmd, READ_SINGLE_BLOCK | WRITE_BLOCK);
+        if self.bus_mode().is_ddr() && single_block && length != BLOCK_SIZE {
+            return Err(TransportError::NotSupported);
+        }
 
-        let rsptyp = match command.response_len() {
-            ResponseLen::Zero => 0,
-            ResponseLen::R136 => 1,
-            ResponseLen::R48 => 2,
-            // TODO not signaling "check busy" for R1b / R5b.
-            // As of this writing, this information is not
-            // available in sdio-host.
+        // An abort command ends any active data transfer.
+        let cmdtyp = if command.cmd == STOP_TRANSMISSION {
+            3
+        } else {
+            0
         };
 
-        ral::modify_reg!(ral, self.inst, MIX_CTRL, DTDSEL: data.is_read() as u32);
-        ral::write_reg!(ral, self.inst, BLK_ATT, BLKSIZE: data.len() as u32);
+        let (auto_cmd12, auto_cmd23) = match self.multi_block {
+            _ if !multi_block => (false, None),
+            MultiBlockMode::AutoCmd12 => (true, None),
+            MultiBlockMode::AutoCmd23(flags) => (false, Some(flags)),
+        };
+
+        // prepare_dma moves these transfers with the CPU, or with ADMA.
+        let argument2 = self.auto_cmd23_flags(command.cmd);
+        if let Some(flags) = argument2 {
+            ral::write_reg!(ral, self.inst, DS_ADDR, flags.bits() | block_count as u32);
+        }
+        ral::modify_reg!(ral, self.inst, VEND_SPEC2, ACMD23_ARGU2_EN: argument2.is_some() as u32);
+
+        ral::modify_reg!(ral, self.inst, MIX_CTRL,
+            DTDSEL: read as u32,
+            DMAEN: matches!(path, DataPath::Dma(_)) as u32,
+            MSBSEL: multi_block as u32,
+            BCEN: multi_block as u32,
+            AC12EN: auto_cmd12 as u32,
+            AC23EN: auto_cmd23.is_some() as u32
+        );
+        ral::write_reg!(ral, self.inst, BLK_ATT,
+            BLKSIZE: block_size as u32,
+            BLKCNT: block_count as u32
+        );
         ral::write_reg!(ral, self.inst, CMD_ARG, command.arg);
+        if !matches!(path, DataPath::None) {
+            self.suspend_card_detect();
+        }
         ral::write_reg!(ral, self.inst, CMD_XFR_TYP,
             CMDINX: command.cmd as u32,
-            CMDTYP: 0, // TODO may need to derive this from command index...
-            DPSEL: !data.is_none() as u32,
+            CMDTYP: cmdtyp,
+            DPSEL: !matches!(path, DataPath::None) as u32,
             CICEN: R::COMMAND_INDEX as u32,
             CCCEN: R::CRC as u32,
             RSPTYP: rsptyp
         );
 
-        self.wait_for(Status::CC)?;
+        Ok(busy)
+    }
 
-        match command.response_len() {
+    /// Returns the flags for an automatic CMD23 ahead of this command, if
+    /// there are any.
+    ///
+    /// With flags, the CMD23 argument comes from the simple DMA address
+    /// register.
+    fn auto_cmd23_flags(&self, command: u8) -> Option<SetBlockCount> {
+        match self.multi_block {
+            MultiBlockMode::AutoCmd23(flags)
+                if !flags.is_empty()
+                    && matches!(command, READ_MULTIPLE_BLOCK | WRITE_MULTIPLE_BLOCK) =>
+            {
+                Some(flags)
+            }
+            _ => None,
+        }
+    }
+
+    /// Read the command response once the command completes.
+    pub(crate) fn read_response(&self, response_len: ResponseLen, response: &mut [u32; 4]) {
+        match response_len {
             ResponseLen::Zero => {}
             ResponseLen::R48 => {
                 response[0] = ral::read_reg!(ral, self.inst, CMD_RSP0);
@@ -154,45 +486,254 @@ impl BlockingSdioTransport for Usdhc {
                 response[0] <<= 8;
             }
         };
+    }
+
+    pub(crate) fn wait_for(&mut self, flags: Status) -> Result<(), TransportError> {
+        self.wait_for_any(flags)?;
+        self.clear_status(flags);
+        Ok(())
+    }
+
+    /// Wait for any of the flags, returning the flags that are set.
+    ///
+    /// Unlike `wait_for`, this doesn't clear the flags.
+    fn wait_for_any(&mut self, flags: Status) -> Result<Status, TransportError> {
+        self.poll(|usdhc| {
+            let status = usdhc.status();
+            if usdhc.detector.is_some() && status.intersects(Status::CRM) {
+                Some(Err(NoCard.into()))
+            } else if status.is_error() {
+                Some(Err(transport_error(status, usdhc.auto_cmd12_error())))
+            } else if status.intersects(flags) {
+                Some(Ok(status & flags))
+            } else {
+                None
+            }
+        })
+        .unwrap_or(Err(TimeoutError::Status(flags).into()))
+    }
 
-        match data {
-            TransportData::Read { buffer } => self.read_into(buffer)?,
-            TransportData::Write { buffer } => self.write_from(buffer)?,
-            TransportData::None => (),
+    /// Wait for a simple DMA transfer to complete.
+    fn wait_for_simple_dma(&mut self) -> Result<(), TransportError> {
+        loop {
+            let status = self.wait_for_any(Status::TC | Status::DINT)?;
+            if self.advance_simple_dma(status) {
+                return Ok(());
+            }
         }
+    }
 
-        Ok(())
+    /// Handle the status of a simple DMA transfer. Returns `true` once the
+    /// transfer completes.
+    ///
+    /// The DMA pauses when it reaches a buffer boundary, and signals a DMA
+    /// interrupt. Writing the next address restarts the DMA.
+    pub(crate) fn advance_simple_dma(&self, status: Status) -> bool {
+        if status.intersects(Status::TC) {
+            self.clear_status(Status::TC | Status::DINT);
+            return true;
+        }
+        if status.intersects(Status::DINT) {
+            self.clear_status(Status::DINT);
+            let next = ral::read_reg!(ral, self.inst, DS_ADDR);
+            ral::write_reg!(ral, self.inst, DS_ADDR, next);
+        }
+        false
     }
 
-    fn power_cycle(&mut self, delay: &mut impl FnMut(u32)) -> Result<(), TransportError> {
-        // Reset the device by driving the reset line.
-        self.set_hardware_reset(false);
-        delay(100);
-        self.set_hardware_reset(true);
+    /// Perform one step of the power cycle.
+    ///
+    /// Returns the milliseconds to wait before the next step, or `None` once
+    /// the power cycle is complete. The blocking and async transports share
+    /// this sequence, and wait in their own way.
+    pub(crate) fn power_cycle_step(&mut self, step: u32) -> Result<Option<u32>, TransportError> {
+        match step {
+            0 => {
+                // Reset the device by driving the reset line.
+                self.set_hardware_reset(false);
+                Ok(Some(100))
+            }
+            1 => {
+                self.set_hardware_reset(true);
+                self.reset_for_identification()?;
+                // Wait before sending the ~80 clock cycles to the card.
+                Ok(Some(5))
+            }
+            2 => {
+                self.initialize_card();
+                // Let that settle...
+                Ok(Some(5))
+            }
+            _ => Ok(None),
+        }
+    }
 
+    /// Reset the peripheral, then prepare it to identify a card.
+    ///
+    /// This is the part of a power cycle that doesn't wait on the card.
+    fn reset_for_identification(&mut self) -> Result<(), TransportError> {
         // Software reset the entire peripheral.
-        self.software_reset();
+        self.software_reset()?;
 
         // Make sure we can see all the status flags.
         self.set_status_enable(Status::all());
         // (Should have been cleared from reset, but
         // you never know...)
         self.clear_status(Status::all());
-        // Don't enable any interrupts.
+        // Don't enable any interrupts, and forget any non-blocking transfer.
         self.set_status_interrupt(Status::empty());
-        // No DMA in this blocking implementation...
-        self.set_dma_enable(None);
+        self.engine = crate::interrupt::Engine::new();
+        // Restore the user's DMA selection.
+        self.set_dma_enable(self.dma);
+        // The card is back to 3.3V signaling.
+        ral::modify_reg!(ral, self.inst, VEND_SPEC, VSELECT: 0);
+        // The reset restored the default clock, which may be too fast
+        // for identification.
+        self.set_bus_mode(BusMode::Identification)?;
+        // The reset also deselected the card detect signal.
+        self.apply_card_detect();
+        // Fail fast if there's no card to identify.
+        self.check_card()?;
 
-        // Send the ~80 clock cycles to the card.
-        delay(5);
-        self.initialize_card();
-        // Let that settle...
-        delay(5);
+        Ok(())
+    }
+
+    /// Correct execution depends on watermark levels. See the `transfer`
+    /// implementation for more details.
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), TransportError> {
+        for bytes in buffer.chunks_exact_mut(4) {
+            self.wait_for(Status::BRR)?;
+
+            let word = self.read_data_buffer();
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
 
         Ok(())
     }
 
+    /// Correct execution depends on watermark levels. See the `transfer`
+    /// implementation for more details.
+    fn write_from(&mut self, buffer: &[u8]) -> Result<(), TransportError> {
+        for bytes in buffer.chunks_exact(4) {
+            self.wait_for(Status::BWR)?;
+
+            let word = u32::from_le_bytes(bytes.try_into().unwrap());
+            self.write_data_buffer(word);
+        }
+
+        Ok(())
+    }
+}
+
+/// Implements the blocking SDIO transport.
+///
+/// # Multi-block transfers
+///
+/// Use CMD18 (`READ_MULTIPLE_BLOCK`) and CMD25 (`WRITE_MULTIPLE_BLOCK`) to transfer
+/// more than one 512 byte block. The peripheral counts the blocks, and either sends
+/// CMD12 once all blocks transfer, or CMD23 before the transfer starts. See
+/// [`set_multi_block_mode`](Usdhc::set_multi_block_mode) to select the behavior.
+/// If the automatic command fails, check [`auto_cmd12_error`](Usdhc::auto_cmd12_error)
+/// for more information.
+///
+/// # Bus modes
+///
+/// Power cycle selects [`BusMode::Identification`]. `set_mode` only understands
+/// SD cards: [`TransportMode::DefaultSpeed`] selects [`BusMode::SdDefaultSpeed`],
+/// and [`TransportMode::HighSpeed`] selects [`BusMode::SdHighSpeed`], which runs
+/// at most 50 MHz. `set_mode` returns [`TransportError::NotSupported`] for any
+/// other mode.
+///
+/// Select all other modes with [`set_bus_mode`](Usdhc::set_bus_mode). That
+/// includes the UHS-I modes, the SD DDR50 mode, and all eMMC modes. For
+/// instance, eMMC high speed requires
+/// `set_bus_mode(BusMode::MmcHighSpeed)` for its 52 MHz clock.
+///
+/// In DDR modes, the transport rejects a 1-bit bus, and single block reads and
+/// writes that aren't 512 bytes.
+///
+/// # Capabilities
+///
+/// The transport consults the host [`Capabilities`](crate::Capabilities). It
+/// rejects bus modes that the host doesn't support, and blocks larger than the
+/// host's maximum block length. If the host doesn't support the selected DMA,
+/// the transport moves data with the CPU.
+///
+/// # DMA errors
+///
+/// If the DMA fails, the transfer returns an uncategorized error. After an
+/// uncategorized error, check [`adma_error`](Usdhc::adma_error) to understand
+/// the failure. For simple DMA, [`Status::DMAE`] indicates the failure.
+///
+/// # Card detection
+///
+/// If you [enable card detection](Usdhc::set_card_detect), the transport checks
+/// for the card before each command, and while it waits on the peripheral. Without
+/// a card, the transport returns a command timeout, since `TransportError` can't
+/// describe a missing card. To tell a missing card from a timeout, use
+/// [`card_transfer`](Usdhc::card_transfer) and [`card_power_cycle`](Usdhc::card_power_cycle).
+///
+/// With [DAT3 detection](crate::CardDetectSource::Dat3), the transport disables
+/// DAT3 detection for each data transfer on a 4-bit or 8-bit bus, and enables it
+/// once the transfer completes.
+///
+/// # Re-tuning
+///
+/// After you [tune](Usdhc::tune) the sampling clock, the transport checks for
+/// a re-tuning request before every command. If the peripheral requests
+/// re-tuning, the transport repeats the tuning before sending the command.
+///
+/// # Assumptions
+///
+/// Power cycle assumes that your reset line controls the hardware. If this isn't
+/// the case, the implementation may not actually power cycle your device. You're
+/// responsible for muxing the reset pin with your IOMUXC peripheral.
+impl BlockingSdioTransport for Usdhc {
+    fn transfer<R>(
+        &mut self,
+        command: &Cmd<R>,
+        response: &mut [u32; 4],
+        data: TransportData<'_>,
+    ) -> Result<(), TransportError>
+    where
+        R: Resp,
+    {
+        self.check_card()?;
+        self.retune_if_needed()?;
+        self.prepare_command()?;
+
+        // For now, always signal whenever one data word is available for
+        // reading. I'm not sure what happens if the data is not a multiple
+        // of four bytes...
+        assert!(
+            data.len() % 4 == 0,
+            "Data lenght must be a multiple of four"
+        );
+
+        let length = data.len();
+        let read = data.is_read();
+        let path = match self.prepare_dma(command.cmd, &data) {
+            Some(dma) => DataPath::Dma(dma),
+            None if data.is_none() => DataPath::None,
+            None => DataPath::Cpu(data),
+        };
+
+        self.execute(command, response, length, read, path)
+    }
+
+    fn power_cycle(&mut self, delay: &mut impl FnMut(u32)) -> Result<(), TransportError> {
+        let mut step = 0;
+        while let Some(ms) = self.power_cycle_step(step)? {
+            delay(ms);
+            step += 1;
+        }
+        Ok(())
+    }
+
     fn set_bus_width(&mut self, bus_width: sdio_host::sd::BusWidth) -> Result<(), TransportError> {
+        if self.bus_mode().is_ddr() && matches!(bus_width, BusWidth::One) {
+            return Err(TransportError::NotSupported);
+        }
         self.set_data_transfer_width(match bus_width {
             BusWidth::One => DataTransferWidth::Bit1,
             BusWidth::Four => DataTransferWidth::Bit4,
@@ -203,8 +744,19 @@ impl BlockingSdioTransport for Usdhc {
         Ok(())
     }
 
-    fn set_mode(&mut self, _: TransportMode) -> Result<(), TransportError> {
-        // TODO actually do a clock / voltage change...
+    /// Select an SD bus mode.
+    ///
+    /// For eMMC and UHS-I modes, use [`set_bus_mode`](Usdhc::set_bus_mode).
+    /// See the implementation documentation for more information.
+    fn set_mode(&mut self, mode: TransportMode) -> Result<(), TransportError> {
+        let mode = match mode {
+            TransportMode::DefaultSpeed => BusMode::SdDefaultSpeed,
+            TransportMode::HighSpeed => BusMode::SdHighSpeed,
+            // Use set_bus_mode for the UHS-I and eMMC modes.
+            #[allow(unreachable_patterns)]
+            _ => return Err(TransportError::NotSupported),
+        };
+        self.set_bus_mode(mode)?;
         Ok(())
     }
 }
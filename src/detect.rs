@@ -0,0 +1,314 @@
+//! Card detection, and card insertion and removal events.
+
+use sdio_host::{common_cmd::Resp, BlockingSdioTransport, Cmd, TransportData};
+
+use crate::{ral, DataTransferWidth, PresentState, Status, TransportError, Usdhc};
+
+/// How the peripheral detects the card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CardDetectSource {
+    /// The card detect switch.
+    #[default]
+    Pin,
+    /// The card's pull-up resistor on DAT3.
+    ///
+    /// Use this if your board doesn't have a card detect switch. DAT3 carries
+    /// data on a 4-bit or 8-bit bus, so the driver disables DAT3 detection
+    /// during each data transfer on a wide bus, and enables it once the
+    /// transfer completes. While DAT3 detection is disabled, the peripheral
+    /// considers the card present.
+    ///
+    /// The card must keep its DAT3 pull-up connected while the driver isn't
+    /// transferring data. Don't disconnect it with ACMD42.
+    Dat3,
+    /// A fixed card detect level, ignoring the board.
+    ///
+    /// `true` indicates that a card is present. This is useful for soldered
+    /// cards, and for testing.
+    TestLevel(bool),
+}
+
+/// Card detection configuration.
+///
+/// See [`set_card_detect`](crate::Usdhc::set_card_detect) to enable card
+/// detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CardDetect {
+    /// How the peripheral detects the card.
+    pub source: CardDetectSource,
+    /// The number of consecutive, matching samples of the card detect level
+    /// before [`poll_card_detect`](crate::Usdhc::poll_card_detect) reports
+    /// a change.
+    ///
+    /// Zero and one report a change on the first sample.
+    pub debounce: u32,
+}
+
+impl Default for CardDetect {
+    fn default() -> Self {
+        Self {
+            source: CardDetectSource::Pin,
+            debounce: 3,
+        }
+    }
+}
+
+/// A card insertion or removal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardEvent {
+    /// A card was inserted.
+    ///
+    /// Power cycle and initialize the card before using it.
+    Inserted,
+    /// The card was removed.
+    Removed,
+}
+
+/// There's no card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoCard;
+
+/// `TransportError` can't describe a missing card. A missing card can't
+/// respond to commands, so it's a command timeout.
+impl From<NoCard> for TransportError {
+    fn from(_: NoCard) -> Self {
+        TransportError::CommandTimeout
+    }
+}
+
+/// An error from a transport that checks for the card.
+///
+/// See [`card_transfer`](crate::Usdhc::card_transfer) for more information.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardError {
+    /// There's no card, or the card was removed during the operation.
+    NoCard,
+    /// The transport failed.
+    Transport(TransportError),
+}
+
+impl From<NoCard> for CardError {
+    fn from(_: NoCard) -> Self {
+        Self::NoCard
+    }
+}
+
+impl From<TransportError> for CardError {
+    fn from(error: TransportError) -> Self {
+        Self::Transport(error)
+    }
+}
+
+impl From<CardError> for TransportError {
+    fn from(error: CardError) -> Self {
+        match error {
+            CardError::NoCard => NoCard.into(),
+            CardError::Transport(error) => error,
+        }
+    }
+}
+
+/// Debounced card presence.
+#[derive(Clone, Copy)]
+pub(crate) struct Detector {
+    config: CardDetect,
+    present: bool,
+    /// Consecutive samples that disagree with `present`.
+    samples: u32,
+}
+
+impl Usdhc {
+    /// Enable or disable card detection.
+    ///
+    /// When enabled, the transport fails fast when there's no card. Before
+    /// each command, and while waiting on the peripheral, the transport checks
+    /// for the card. It fails if the card isn't present, or if the peripheral
+    /// signals [`Status::CRM`]. This includes a power cycle, so initializing a
+    /// host without a card fails.
+    ///
+    /// [`card_transfer`](Self::card_transfer), [`card_power_cycle`](Self::card_power_cycle),
+    /// and the [async transport](crate::AsyncUsdhc) report a missing card as
+    /// [`CardError::NoCard`]. The [`BlockingSdioTransport`] can only report a
+    /// [`TransportError`], so it reports a missing card as a command timeout.
+    ///
+    /// The detector starts with the present card state. By default, card
+    /// detection is disabled, and the transport assumes that a card is present.
+    /// Disabling card detection selects the card detect switch.
+    ///
+    /// The selection persists across power cycles.
+    pub fn set_card_detect(&mut self, config: Option<CardDetect>) {
+        self.detector = config.map(|config| Detector {
+            config,
+            present: false,
+            samples: 0,
+        });
+        self.apply_card_detect();
+        let present = self.present_state().intersects(PresentState::CINST);
+        if let Some(detector) = self.detector.as_mut() {
+            detector.present = present;
+        }
+    }
+
+    /// Returns `true` if there's a card.
+    ///
+    /// With card detection, this is the debounced card presence. Otherwise,
+    /// this reflects [`PresentState::CINST`].
+    pub fn card_present(&self) -> bool {
+        match self.detector {
+            Some(detector) => detector.present,
+            None => self.present_state().intersects(PresentState::CINST),
+        }
+    }
+
+    /// Sample the card detect level, and report a debounced insertion or removal.
+    ///
+    /// With the card detect switch, this samples [`PresentState::CDPL`].
+    /// Otherwise, this samples [`PresentState::CINST`].
+    ///
+    /// Call this periodically. Once [`debounce`](CardDetect::debounce) consecutive
+    /// samples disagree with the card presence, this reports the change. Returns
+    /// `None` if there's no change, or if card detection is disabled.
+    pub fn poll_card_detect(&mut self) -> Option<CardEvent> {
+        let source = self.detector?.config.source;
+        let level = self.present_state().intersects(match source {
+            CardDetectSource::Pin => PresentState::CDPL,
+            _ => PresentState::CINST,
+        });
+        let detector = self.detector.as_mut()?;
+        if level == detector.present {
+            detector.samples = 0;
+            return None;
+        }
+
+        detector.samples += 1;
+        if detector.samples < detector.config.debounce {
+            return None;
+        }
+        detector.samples = 0;
+        detector.present = level;
+        Some(detector.event())
+    }
+
+    /// Handle a card insertion or removal interrupt.
+    ///
+    /// Route [`Status::CINS`] and [`Status::CRM`] to the interrupt with
+    /// [`set_status_interrupt`](Self::set_status_interrupt), then call this
+    /// from your uSDHC interrupt handler. This clears the flags, and reports
+    /// the change. The peripheral debounces these flags, so this doesn't
+    /// apply the software debounce. Returns `None` if there's no change, or
+    /// if card detection is disabled.
+    ///
+    /// Note that a power cycle disables all interrupts.
+    pub fn on_card_detect_interrupt(&mut self) -> Option<CardEvent> {
+        let status = self.status() & (Status::CINS | Status::CRM);
+        self.clear_status(status);
+        let present = self.present_state().intersects(PresentState::CINST);
+        let detector = self.detector.as_mut()?;
+        if status.is_empty() || present == detector.present {
+            return None;
+        }
+        detector.samples = 0;
+        detector.present = present;
+        Some(detector.event())
+    }
+
+    /// Send a command, and transfer its data.
+    ///
+    /// This is the [`BlockingSdioTransport`] transfer, but it reports a missing
+    /// card as [`CardError::NoCard`]. Use this for a hot-plug slot, so that you
+    /// can tell a removed card from a card that didn't respond.
+    pub fn card_transfer<R: Resp>(
+        &mut self,
+        command: &Cmd<R>,
+        response: &mut [u32; 4],
+        data: TransportData<'_>,
+    ) -> Result<(), CardError> {
+        BlockingSdioTransport::transfer(self, command, response, data)
+            .map_err(|error| self.card_error(error))
+    }
+
+    /// Power cycle the card, then prepare to identify the card.
+    ///
+    /// This is the [`BlockingSdioTransport`] power cycle, but it reports a
+    /// missing card as [`CardError::NoCard`].
+    pub fn card_power_cycle(&mut self, delay: &mut impl FnMut(u32)) -> Result<(), CardError> {
+        BlockingSdioTransport::power_cycle(self, delay).map_err(|error| self.card_error(error))
+    }
+
+    /// Explain a transport error.
+    ///
+    /// If card detection is enabled, and the card is gone, or it was removed,
+    /// the error is [`CardError::NoCard`].
+    pub(crate) fn card_error(&self, error: TransportError) -> CardError {
+        let missing = self.detector.is_some()
+            && (!self.present_state().intersects(PresentState::CINST)
+                || self.status().intersects(Status::CRM));
+        if missing {
+            CardError::NoCard
+        } else {
+            CardError::Transport(error)
+        }
+    }
+
+    /// Select the card detect signal for the configuration.
+    pub(crate) fn apply_card_detect(&mut self) {
+        let source = self.detector.map(|detector| detector.config.source);
+        let (d3cd, cdss, cdtl) = match source {
+            None | Some(CardDetectSource::Pin) => (0, 0, 0),
+            Some(CardDetectSource::Dat3) => (1, 0, 0),
+            Some(CardDetectSource::TestLevel(level)) => (0, 1, level as u32),
+        };
+        ral::modify_reg!(ral, self.inst, PROT_CTRL, D3CD: d3cd, CDSS: cdss, CDTL: cdtl);
+    }
+
+    /// Release DAT3 for a data transfer on a wide bus.
+    ///
+    /// The test level keeps the card present while DAT3 carries data.
+    pub(crate) fn suspend_card_detect(&mut self) {
+        let dat3 = self
+            .detector
+            .is_some_and(|detector| detector.config.source == CardDetectSource::Dat3);
+        if dat3 && self.data_transfer_width() != DataTransferWidth::Bit1 {
+            ral::modify_reg!(ral, self.inst, PROT_CTRL, D3CD: 0, CDSS: 1, CDTL: 1);
+        }
+    }
+
+    /// Return to DAT3 detection once the data transfer completes.
+    pub(crate) fn resume_card_detect(&mut self) {
+        let dat3 = self
+            .detector
+            .is_some_and(|detector| detector.config.source == CardDetectSource::Dat3);
+        if dat3 {
+            self.apply_card_detect();
+        }
+    }
+
+    /// Check for the card.
+    ///
+    /// Returns [`NoCard`] if card detection is enabled, and there's no card.
+    /// The transport performs this check before each command.
+    ///
+    /// If the peripheral signaled [`Status::CRM`], but a card is present again,
+    /// this clears the flag. Re-initialize the card after it returns.
+    pub fn check_card(&mut self) -> Result<(), NoCard> {
+        if self.detector.is_none() {
+            return Ok(());
+        }
+        if !self.present_state().intersects(PresentState::CINST) {
+            return Err(NoCard);
+        }
+        // The removal is stale; don't fail the next wait on its account.
+        self.clear_status(self.status() & Status::CRM);
+        Ok(())
+    }
+}
+
+impl Detector {
+    fn event(&self) -> CardEvent {
+        if self.present {
+            CardEvent::Inserted
+        } else {
+            CardEvent::Removed
+        }
+    }
+}
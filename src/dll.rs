@@ -0,0 +1,144 @@
+//! Delay lines (DLL) for data sampling in DDR and HS400 modes.
+
+use crate::{ral, TimeoutError, Usdhc};
+
+/// Delay line configuration.
+///
+/// The delay line delays the card clock before it samples read data. The
+/// implementation truncates each field to the width of its register field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DllConfig {
+    /// The slave delay target, in fractions of the reference clock period.
+    ///
+    /// This is a 7-bit value.
+    pub delay_target: u8,
+    /// Use this slave delay, in delay cells, instead of the locked delay.
+    ///
+    /// This is a 7-bit value. When set, the DLL doesn't need to lock.
+    pub override_delay: Option<u8>,
+    /// Slave delay line update interval, in reference clock cycles.
+    pub slave_update_interval: u8,
+    /// Reference delay line update interval.
+    ///
+    /// This is a 4-bit value.
+    pub reference_update_interval: u8,
+}
+
+/// Delay line status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DllStatus {
+    /// The slave delay line is locked.
+    pub slave_lock: bool,
+    /// The reference delay line is locked.
+    pub reference_lock: bool,
+    /// The delay cells selected by the slave delay line.
+    pub slave_select: u8,
+    /// The delay cells selected by the reference delay line.
+    pub reference_select: u8,
+}
+
+impl Usdhc {
+    /// Reset, configure, and enable the delay line.
+    ///
+    /// Unless the configuration overrides the delay, this blocks until the
+    /// delay line locks, or until the [`Timeout`](crate::Timeout) expires.
+    pub fn enable_dll(&mut self, config: DllConfig) -> Result<(), TimeoutError> {
+        let target = config.delay_target as u32;
+        ral::write_reg!(ral, self.inst, DLL_CTRL, DLL_CTRL_RESET: 1);
+        ral::write_reg!(ral, self.inst, DLL_CTRL,
+            DLL_CTRL_SLV_DLY_TARGET0: target,
+            DLL_CTRL_SLV_DLY_TARGET1: target >> 4,
+            DLL_CTRL_SLV_OVERRIDE: config.override_delay.is_some() as u32,
+            DLL_CTRL_SLV_OVERRIDE_VAL: config.override_delay.unwrap_or(0) as u32,
+            DLL_CTRL_SLV_UPDATE_INT: config.slave_update_interval as u32,
+            DLL_CTRL_REF_UPDATE_INT: config.reference_update_interval as u32
+        );
+        ral::modify_reg!(ral, self.inst, DLL_CTRL, DLL_CTRL_ENABLE: 1);
+        ral::modify_reg!(ral, self.inst, DLL_CTRL, DLL_CTRL_SLV_FORCE_UPD: 1);
+        ral::modify_reg!(ral, self.inst, DLL_CTRL, DLL_CTRL_SLV_FORCE_UPD: 0);
+
+        if config.override_delay.is_none() {
+            self.wait_for_dll_lock()?;
+        }
+        Ok(())
+    }
+
+    /// Disable the delay line.
+    pub fn disable_dll(&mut self) {
+        ral::write_reg!(ral, self.inst, DLL_CTRL, 0);
+    }
+
+    /// Read the delay line status.
+    pub fn dll_status(&self) -> DllStatus {
+        let (slave_lock, reference_lock, slave_select, reference_select) = ral::read_reg!(
+            ral,
+            self.inst,
+            DLL_STATUS,
+            DLL_STS_SLV_LOCK,
+            DLL_STS_REF_LOCK,
+            DLL_STS_SLV_SEL,
+            DLL_STS_REF_SEL
+        );
+        DllStatus {
+            slave_lock: slave_lock != 0,
+            reference_lock: reference_lock != 0,
+            slave_select: slave_select as u8,
+            reference_select: reference_select as u8,
+        }
+    }
+
+    /// Reset, configure, and enable the strobe delay line.
+    ///
+    /// The strobe delay line delays the HS400 data strobe. `delay_target` is
+    /// a 4-bit slave delay target. Blocks until the delay line locks, or until
+    /// the [`Timeout`](crate::Timeout) expires.
+    pub fn enable_strobe_dll(&mut self, delay_target: u8) -> Result<(), TimeoutError> {
+        ral::write_reg!(ral, self.inst, STROBE_DLL_CTRL, STROBE_DLL_CTRL_RESET: 1);
+        ral::write_reg!(ral, self.inst, STROBE_DLL_CTRL, 0);
+        ral::write_reg!(ral, self.inst, STROBE_DLL_CTRL,
+            STROBE_DLL_CTRL_ENABLE: 1,
+            STROBE_DLL_CTRL_SLV_DLY_TARGET: delay_target as u32
+        );
+        self.poll(|usdhc| {
+            let status = usdhc.strobe_dll_status();
+            (status.slave_lock && status.reference_lock).then_some(())
+        })
+        .ok_or(TimeoutError::StrobeDllLock)
+    }
+
+    /// Disable the strobe delay line.
+    pub fn disable_strobe_dll(&mut self) {
+        ral::write_reg!(ral, self.inst, STROBE_DLL_CTRL, 0);
+    }
+
+    /// Read the strobe delay line status.
+    pub fn strobe_dll_status(&self) -> DllStatus {
+        let (slave_lock, reference_lock, slave_select, reference_select) = ral::read_reg!(
+            ral,
+            self.inst,
+            STROBE_DLL_STATUS,
+            STROBE_DLL_STS_SLV_LOCK,
+            STROBE_DLL_STS_REF_LOCK,
+            STROBE_DLL_STS_SLV_SEL,
+            STROBE_DLL_STS_REF_SEL
+        );
+        DllStatus {
+            slave_lock: slave_lock != 0,
+            reference_lock: reference_lock != 0,
+            slave_select: slave_select as u8,
+            reference_select: reference_select as u8,
+        }
+    }
+
+    /// Wait for both the reference and slave delay lines to lock.
+    ///
+    /// Blocks until the delay line locks, or until the [`Timeout`](crate::Timeout)
+    /// expires.
+    pub fn wait_for_dll_lock(&self) -> Result<(), TimeoutError> {
+        self.poll(|usdhc| {
+            let status = usdhc.dll_status();
+            (status.slave_lock && status.reference_lock).then_some(())
+        })
+        .ok_or(TimeoutError::DllLock)
+    }
+}
@@ -0,0 +1,123 @@
+//! eMMC HS400 bring-up.
+
+use sdio_host::{
+    common_cmd::{cmd, R1},
+    BlockingSdioTransport, TransportData,
+};
+
+use crate::{
+    ral, BusMode, DataTransferWidth, ModeError, StandardTuning, TimeoutError, TransportError,
+    TuningCommand, TuningError, Usdhc,
+};
+
+/// CMD6, SWITCH.
+const SWITCH: u8 = 6;
+/// SWITCH access mode that writes a byte.
+const WRITE_BYTE: u32 = 0b11;
+
+/// EXT_CSD BUS_WIDTH index, and the 8-bit DDR value.
+const BUS_WIDTH: u8 = 183;
+const BUS_WIDTH_8_DDR: u8 = 6;
+
+/// EXT_CSD HS_TIMING index, and its values.
+const HS_TIMING: u8 = 185;
+const HS_TIMING_HS: u8 = 1;
+const HS_TIMING_HS200: u8 = 2;
+const HS_TIMING_HS400: u8 = 3;
+
+/// An error when switching to HS400.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Hs400Error {
+    /// The host isn't using an 8-bit bus.
+    BusWidth,
+    /// The device didn't accept a SWITCH command.
+    Switch(TransportError),
+    /// Tuning failed in HS200.
+    Tuning(TuningError),
+    /// The host couldn't select a bus mode.
+    Mode(ModeError),
+    /// The strobe delay line never locked.
+    Timeout(TimeoutError),
+}
+
+impl From<TuningError> for Hs400Error {
+    fn from(error: TuningError) -> Self {
+        Self::Tuning(error)
+    }
+}
+
+impl From<ModeError> for Hs400Error {
+    fn from(error: ModeError) -> Self {
+        Self::Mode(error)
+    }
+}
+
+impl From<TimeoutError> for Hs400Error {
+    fn from(timeout: TimeoutError) -> Self {
+        Self::Timeout(timeout)
+    }
+}
+
+impl Usdhc {
+    /// Switch an eMMC device, and the host, to HS400.
+    ///
+    /// This tunes the sampling clock in HS200, drops to high speed, selects
+    /// the 8-bit DDR bus, then selects HS400. Once in HS400, it locks the
+    /// strobe delay line using `strobe_delay_target`, a 4-bit slave delay
+    /// target. Returns the card clock frequency.
+    ///
+    /// Before calling this, select the device, switch the device and the host
+    /// to an 8-bit bus, and make sure the device supports HS400. The root clock
+    /// should be at least 400 MHz to reach the fastest HS400 clock.
+    ///
+    /// The driver can't re-tune in HS400. To re-tune, power cycle, and repeat
+    /// the bring-up.
+    pub fn enable_hs400(
+        &mut self,
+        tuning: StandardTuning,
+        strobe_delay_target: u8,
+    ) -> Result<u32, Hs400Error> {
+        if self.data_transfer_width() != DataTransferWidth::Bit8 {
+            return Err(Hs400Error::BusWidth);
+        }
+
+        self.switch(HS_TIMING, HS_TIMING_HS200)?;
+        self.set_bus_mode(BusMode::MmcHs200)?;
+        self.tune(
+            TuningCommand::MmcCmd21,
+            StandardTuning {
+                auto_tune: false,
+                ..tuning
+            },
+        )?;
+        // Keep the tuned sampling clock, but don't re-tune. The device doesn't
+        // accept tuning commands once it leaves HS200.
+        self.tuned = None;
+
+        self.switch(HS_TIMING, HS_TIMING_HS)?;
+        self.apply_bus_mode(BusMode::MmcHighSpeed)?;
+        self.switch(BUS_WIDTH, BUS_WIDTH_8_DDR)?;
+        self.switch(HS_TIMING, HS_TIMING_HS400)?;
+
+        ral::modify_reg!(ral, self.inst, VEND_SPEC2,
+            HS400_WR_CLK_STOP_EN: 1,
+            HS400_RD_CLK_STOP_EN: 1
+        );
+        let hz = self.apply_bus_mode(BusMode::MmcHs400)?;
+        self.enable_strobe_dll(strobe_delay_target)?;
+        Ok(hz)
+    }
+
+    /// Write a byte of the device's EXT_CSD.
+    fn switch(&mut self, index: u8, value: u8) -> Result<(), Hs400Error> {
+        let argument = WRITE_BYTE << 24 | (index as u32) << 16 | (value as u32) << 8;
+        let mut response = [0; 4];
+        self.transfer(
+            &cmd::<R1>(SWITCH, argument),
+            &mut response,
+            TransportData::None,
+        )
+        .map_err(Hs400Error::Switch)
+    }
+}
@@ -0,0 +1,363 @@
+//! Interrupt-driven, non-blocking transfers.
+
+use sdio_host::{
+    common_cmd::{Resp, ResponseLen},
+    Cmd, TransportData,
+};
+
+use crate::{
+    blocking::{transport_error, DataPath},
+    DmaSelect, Status, TimeoutError, TransportError, Usdhc,
+};
+
+/// The state of a non-blocking transfer.
+///
+/// See [`start_transfer`](Usdhc::start_transfer) for more information.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TransferState {
+    /// There's no transfer.
+    Idle,
+    /// The command is active. The driver is waiting for the response.
+    Command,
+    /// The data phase is active.
+    Data,
+    /// The card is signaling busy on DAT0.
+    Busy,
+    /// The transfer completed.
+    Complete,
+    /// The transfer failed.
+    Error(TransportError),
+}
+
+/// How the engine moves data after the response.
+#[derive(Clone, Copy)]
+enum Data {
+    /// There's no data phase.
+    None,
+    /// The CPU copies words between the data buffer and memory.
+    Cpu {
+        address: usize,
+        length: usize,
+        position: usize,
+        read: bool,
+    },
+    /// The internal DMA moves the data.
+    Dma(DmaSelect),
+}
+
+/// State for the non-blocking transfer.
+pub(crate) struct Engine {
+    state: TransferState,
+    response_len: ResponseLen,
+    response: [u32; 4],
+    busy: bool,
+    data: Data,
+    /// The status flags that the engine routes to the interrupt.
+    signals: Status,
+}
+
+impl Engine {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: TransferState::Idle,
+            response_len: ResponseLen::Zero,
+            response: [0; 4],
+            busy: false,
+            data: Data::None,
+            signals: Status::empty(),
+        }
+    }
+}
+
+impl Usdhc {
+    /// Start a transfer without waiting for it to complete.
+    ///
+    /// This prepares the data phase and sends the command. Then, call
+    /// [`on_interrupt`](Self::on_interrupt) from your uSDHC interrupt
+    /// handler to advance the transfer. Once the transfer is
+    /// [`Complete`](TransferState::Complete) or fails, take the response with
+    /// [`transfer_result`](Self::transfer_result). While the transfer is
+    /// active, your CPU can wait for interrupts.
+    ///
+    /// The driver enables the uSDHC interrupt signals that it needs, and
+    /// disables them once the transfer finishes. It preserves any other
+    /// [interrupt signals](Self::set_status_interrupt). You're responsible
+    /// for unmasking the uSDHC interrupt in your interrupt controller.
+    ///
+    /// Like the blocking transport, this uses the selected DMA when it can,
+    /// and it [re-tunes](Self::retune_if_needed) before the command. Those
+    /// steps, and the wait for the command and data lines, block.
+    ///
+    /// Returns an error if a transfer is already active, or if the driver
+    /// can't start the transfer.
+    ///
+    /// # Safety
+    ///
+    /// The peripheral, or the interrupt handler, accesses the buffer in `data`
+    /// after this call returns. The buffer must stay valid, and you must not
+    /// access it, until the transfer finishes or you
+    /// [abort](Self::abort_transfer) the transfer.
+    pub unsafe fn start_transfer<R: Resp>(
+        &mut self,
+        command: &Cmd<R>,
+        data: TransportData<'_>,
+    ) -> Result<(), TransportError> {
+        if self.transfer_state() != TransferState::Idle {
+            return Err(TransportError::uncategorized());
+        }
+
+        self.check_card()?;
+        self.retune_if_needed()?;
+        self.prepare_command()?;
+
+        assert!(
+            data.len() % 4 == 0,
+            "Data length must be a multiple of four"
+        );
+
+        let length = data.len();
+        let read = data.is_read();
+        let (path, engine_data) = match self.prepare_dma(command.cmd, &data) {
+            Some(dma) => (DataPath::Dma(dma), Data::Dma(dma)),
+            None => {
+                let address = match &data {
+                    TransportData::Read { buffer } => buffer.as_ptr() as usize,
+                    TransportData::Write { buffer } => buffer.as_ptr() as usize,
+                    TransportData::None => 0,
+                };
+                let engine_data = if data.is_none() {
+                    Data::None
+                } else {
+                    Data::Cpu {
+                        address,
+                        length,
+                        position: 0,
+                        read,
+                    }
+                };
+                let path = if data.is_none() {
+                    DataPath::None
+                } else {
+                    DataPath::Cpu(data)
+                };
+                (path, engine_data)
+            }
+        };
+
+        let signals = Status::CC | Status::ERRORS;
+        self.engine = Engine {
+            state: TransferState::Command,
+            response_len: command.response_len(),
+            response: [0; 4],
+            busy: false,
+            data: engine_data,
+            signals,
+        };
+
+        match self.issue(command, length, read, &path) {
+            Ok(busy) => {
+                self.engine.busy = busy;
+                self.set_status_interrupt(self.status_interrupt() | signals);
+                Ok(())
+            }
+            Err(error) => {
+                self.engine = Engine::new();
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns the state of the non-blocking transfer.
+    #[inline]
+    pub fn transfer_state(&self) -> TransferState {
+        self.engine.state
+    }
+
+    /// Advance the non-blocking transfer.
+    ///
+    /// Call this from your uSDHC interrupt handler. It handles the status
+    /// flags for the active transfer, moves any data for the CPU, and returns
+    /// the new state. It leaves all other status flags alone.
+    ///
+    /// If there's no active transfer, this does nothing.
+    pub fn on_interrupt(&mut self) -> TransferState {
+        loop {
+            let status = self.status();
+            let next = match self.engine.state {
+                TransferState::Command | TransferState::Data | TransferState::Busy
+                    if status.is_error() =>
+                {
+                    Some(TransferState::Error(transport_error(
+                        status,
+                        self.auto_cmd12_error(),
+                    )))
+                }
+                TransferState::Command if status.intersects(Status::CC) => {
+                    self.clear_status(Status::CC);
+                    let mut response = [0; 4];
+                    self.read_response(self.engine.response_len, &mut response);
+                    self.engine.response = response;
+                    Some(match self.engine.data {
+                        Data::None if self.engine.busy => TransferState::Busy,
+                        Data::None => TransferState::Complete,
+                        Data::Cpu { .. } | Data::Dma(_) => TransferState::Data,
+                    })
+                }
+                TransferState::Data => match self.engine.data {
+                    Data::Cpu { .. } => self.move_data(status),
+                    Data::Dma(DmaSelect::Simple)
+                        if status.intersects(Status::TC | Status::DINT) =>
+                    {
+                        Some(if self.advance_simple_dma(status) {
+                            TransferState::Complete
+                        } else {
+                            TransferState::Data
+                        })
+                    }
+                    _ if status.intersects(Status::TC) => {
+                        self.clear_status(Status::TC | Status::DINT);
+                        Some(TransferState::Complete)
+                    }
+                    _ => None,
+                },
+                TransferState::Busy if status.intersects(Status::TC) => {
+                    self.clear_status(Status::TC);
+                    Some(TransferState::Complete)
+                }
+                _ => None,
+            };
+
+            match next {
+                Some(state) => self.set_transfer_state(state),
+                None => return self.engine.state,
+            }
+        }
+    }
+
+    /// Take the result of a finished transfer.
+    ///
+    /// Returns the command response once the transfer completes, or the
+    /// error if it failed. In either case, the driver is ready for the next
+    /// transfer. Returns `None` if the transfer is still active, or if there's
+    /// no transfer.
+    ///
+    /// If the DMA fails, the error is uncategorized. Check
+    /// [`adma_error`](Self::adma_error) to understand the failure.
+    pub fn transfer_result(&mut self) -> Option<Result<[u32; 4], TransportError>> {
+        let result = match self.engine.state {
+            TransferState::Complete => Ok(self.engine.response),
+            TransferState::Error(error) => Err(error),
+            _ => return None,
+        };
+        self.engine = Engine::new();
+        Some(result)
+    }
+
+    /// Abort the non-blocking transfer.
+    ///
+    /// This disables the transfer's interrupt signals, and resets the command
+    /// and data lines. Once this returns, the driver no longer accesses the
+    /// transfer's buffer.
+    pub fn abort_transfer(&mut self) -> Result<(), TimeoutError> {
+        if self.engine.state == TransferState::Idle {
+            return Ok(());
+        }
+        self.set_status_interrupt(self.status_interrupt() - self.engine.signals);
+        self.engine = Engine::new();
+        self.resume_card_detect();
+        self.command_reset()?;
+        self.data_reset()
+    }
+
+    /// Re-enable the interrupts for the transfer's state.
+    ///
+    /// Use this after an interrupt handler masks the signals.
+    pub(crate) fn arm_transfer_interrupts(&self) {
+        self.set_status_interrupt(self.status_interrupt() | self.engine.signals);
+    }
+
+    /// Move one word between the buffer and the data buffer.
+    ///
+    /// Returns the next state, or `None` if the data phase needs to wait.
+    fn move_data(&mut self, status: Status) -> Option<TransferState> {
+        let Data::Cpu {
+            address,
+            length,
+            position,
+            read,
+        } = self.engine.data
+        else {
+            return None;
+        };
+
+        if position == length {
+            return status.intersects(Status::TC).then(|| {
+                self.clear_status(Status::TC);
+                TransferState::Complete
+            });
+        }
+
+        let ready = if read { Status::BRR } else { Status::BWR };
+        if !status.intersects(ready) {
+            return None;
+        }
+        self.clear_status(ready);
+
+        // Safety: the caller of start_transfer guarantees that the buffer
+        // is valid, and that we have exclusive access, until the transfer
+        // finishes.
+        let word = (address + position) as *mut [u8; 4];
+        if read {
+            let bytes = self.read_data_buffer().to_le_bytes();
+            unsafe { word.write_unaligned(bytes) };
+        } else {
+            let bytes = unsafe { word.read_unaligned() };
+            self.write_data_buffer(u32::from_le_bytes(bytes));
+        }
+
+        self.engine.data = Data::Cpu {
+            address,
+            length,
+            position: position + 4,
+            read,
+        };
+        Some(TransferState::Data)
+    }
+
+    /// Update the state, and the interrupt signals for that state.
+    fn set_transfer_state(&mut self, state: TransferState) {
+        let signals = match state {
+            TransferState::Command => Status::CC | Status::ERRORS,
+            TransferState::Data => match self.engine.data {
+                Data::Cpu {
+                    length,
+                    position,
+                    read,
+                    ..
+                } if position < length => {
+                    let ready = if read { Status::BRR } else { Status::BWR };
+                    ready | Status::TC | Status::ERRORS
+                }
+                Data::Dma(DmaSelect::Simple) => Status::DINT | Status::TC | Status::ERRORS,
+                _ => Status::TC | Status::ERRORS,
+            },
+            TransferState::Busy => Status::TC | Status::ERRORS,
+            _ => Status::empty(),
+        };
+
+        if matches!(state, TransferState::Complete | TransferState::Error(_)) {
+            self.resume_card_detect();
+        }
+        if let TransferState::Error(_) = state {
+            // Clear the failed command and data phase for the next transfer.
+            // The state keeps the original error, even if a reset times out.
+            let _ = self.command_reset().and_then(|()| self.data_reset());
+        }
+
+        let others = self.status_interrupt() - self.engine.signals;
+        self.set_status_interrupt(others | signals);
+        self.engine.signals = signals;
+        self.engine.state = state;
+    }
+}
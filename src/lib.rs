@@ -6,10 +6,46 @@
 #![no_std]
 #![deny(missing_docs, unsafe_op_in_unsafe_fn)]
 
+#[cfg(feature = "sim")]
+extern crate std;
+
+// The simulator replaces the register access layer for the entire crate.
+#[cfg(all(feature = "sim", target_os = "none"))]
+compile_error!(
+    "The \"sim\" feature can't access the uSDHC peripheral. Don't enable it in firmware."
+);
+
+mod adma;
+mod asynch;
 mod blocking;
+mod detect;
+mod dll;
+mod hs400;
+mod interrupt;
 mod ral;
+#[cfg(feature = "sim")]
+pub mod sim;
+mod storage;
+mod timeout;
+mod tuning;
+mod voltage;
+mod waker;
 
-pub use blocking::{BlockingSdioHost, HostError, TransportError};
+pub use adma::{
+    Adma1Action, Adma1Descriptor, Adma1Table, Adma2Descriptor, Adma2Table, AdmaError, AdmaState,
+    TableError,
+};
+pub use asynch::AsyncUsdhc;
+pub use blocking::{BlockingSdioHost, HostError, TransportError, VectoredData, VectoredError};
+pub use detect::{CardDetect, CardDetectSource, CardError, CardEvent, NoCard};
+pub use dll::{DllConfig, DllStatus};
+pub use hs400::Hs400Error;
+pub use interrupt::TransferState;
+pub use storage::BlockStorage;
+pub use timeout::{Timeout, TimeoutError};
+pub use tuning::{StandardTuning, TapMap, TuningCommand, TuningError};
+pub use voltage::VoltageSwitchError;
+pub use waker::AtomicWaker;
 
 /// The size, in bits, for a data transfer.
 ///
@@ -32,6 +68,16 @@ pub enum DataTransferWidth {
 pub enum DmaSelect {
     /// Simple DMA support.
     Simple = 0,
+    /// Advanced DMA, version 1.
+    ///
+    /// Requires an [`Adma1Table`]. See [`set_adma1_table`](Usdhc::set_adma1_table)
+    /// for more information.
+    Adma1 = 1,
+    /// Advanced DMA, version 2.
+    ///
+    /// Requires an [`Adma2Table`]. See [`set_adma2_table`](Usdhc::set_adma2_table)
+    /// for more information.
+    Adma2 = 2,
 }
 
 bitflags::bitflags! {
@@ -136,6 +182,76 @@ impl Status {
     }
 }
 
+bitflags::bitflags! {
+    /// Auto CMD12 error flags.
+    ///
+    /// When [`Status::AC12E`] is set, these flags describe what went wrong
+    /// with the automatic CMD12.
+    pub struct AutoCmd12Error: u32 {
+        /// Command not issued by auto CMD12 error.
+        ///
+        /// The auto CMD12 was not sent, because a command error occurred
+        /// with the previous command.
+        const CNIBAC12E = 1 << 7;
+        /// Auto CMD12 index error.
+        ///
+        /// The command index in the response didn't match CMD12.
+        const AC12IE = 1 << 4;
+        /// Auto CMD12 CRC error.
+        const AC12CE = 1 << 3;
+        /// Auto CMD12 end bit error.
+        const AC12EBE = 1 << 2;
+        /// Auto CMD12 timeout error.
+        ///
+        /// There was no response to the auto CMD12.
+        const AC12TOE = 1 << 1;
+        /// Auto CMD12 not executed.
+        ///
+        /// The auto CMD12 couldn't be sent due to an error in the data phase.
+        const AC12NE = 1 << 0;
+    }
+}
+
+/// Describes how the peripheral ends multi-block transfers.
+///
+/// See [`set_multi_block_mode`](Usdhc::set_multi_block_mode) for more information.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MultiBlockMode {
+    /// Open-ended transfers.
+    ///
+    /// The peripheral sends CMD12 (`STOP_TRANSMISSION`) after the last block.
+    /// This is the default behavior, and it works with all cards.
+    #[default]
+    AutoCmd12,
+    /// Closed-ended, pre-defined transfers.
+    ///
+    /// The peripheral sends CMD23 (`SET_BLOCK_COUNT`) before the transfer
+    /// command. The card must support CMD23. The flags are included in the
+    /// CMD23 argument, alongside the block count.
+    AutoCmd23(SetBlockCount),
+}
+
+bitflags::bitflags! {
+    /// Flags for the CMD23 (`SET_BLOCK_COUNT`) argument.
+    ///
+    /// These flags are only defined for eMMC devices. Use an empty set for SD cards.
+    #[derive(Default)]
+    pub struct SetBlockCount: u32 {
+        /// Reliable write request.
+        ///
+        /// Only applies to writes.
+        const RELIABLE_WRITE = 1 << 31;
+        /// The transfer is a packed command.
+        const PACKED = 1 << 30;
+        /// Tag request.
+        const TAG_REQUEST = 1 << 29;
+        /// Forced programming.
+        ///
+        /// Only applies to writes.
+        const FORCED_PROGRAMMING = 1 << 24;
+    }
+}
+
 /// Endianness for the data transfer.
 ///
 /// Describes the types of byte swaps that occur when interfacing the internal
@@ -203,7 +319,257 @@ pub struct Timing {
     ///
     /// This also determines the prescaler for the clock.
     pub data_rate: DataRate,
-    // TODO data timeout.
+    /// Data timeout.
+    ///
+    /// Cards that hold the data line busy for a long time, like
+    /// during erase, may need a longer timeout.
+    pub data_timeout: DataTimeout,
+}
+
+impl Timing {
+    /// Compute the single data rate timing for a card clock.
+    ///
+    /// `root_clock_hz` is the frequency of the uSDHC root clock. Returns the
+    /// timing that achieves the fastest card clock that doesn't exceed
+    /// `card_clock_hz`, along with that card clock frequency. Returns `None`
+    /// if the root clock can't be divided down to the card clock.
+    ///
+    /// The timing uses the default [`DataTimeout`].
+    pub fn sdr(root_clock_hz: u32, card_clock_hz: u32) -> Option<(Self, u32)> {
+        SDRPrescaler::ALL
+            .iter()
+            .filter_map(|&prescaler| {
+                Self::best(
+                    root_clock_hz,
+                    card_clock_hz,
+                    DataRate::SingleDataRate(prescaler),
+                )
+            })
+            .max_by_key(|&(_, hz)| hz)
+    }
+
+    /// Compute the dual data rate timing for a card clock.
+    ///
+    /// See [`sdr`](Self::sdr) for more information. The card clock doesn't
+    /// account for the doubled data rate.
+    pub fn ddr(root_clock_hz: u32, card_clock_hz: u32) -> Option<(Self, u32)> {
+        DDRPrescaler::ALL
+            .iter()
+            .filter_map(|&prescaler| {
+                Self::best(
+                    root_clock_hz,
+                    card_clock_hz,
+                    DataRate::DualDataRate(prescaler),
+                )
+            })
+            .max_by_key(|&(_, hz)| hz)
+    }
+
+    /// Returns the card clock frequency for this timing, given the root clock.
+    pub fn card_clock_hz(&self, root_clock_hz: u32) -> u32 {
+        root_clock_hz / (self.data_rate.prescaler() * self.divisor.clamp(1, 16) as u32)
+    }
+
+    /// Find the smallest divisor for the data rate that doesn't exceed the card clock.
+    fn best(root_clock_hz: u32, card_clock_hz: u32, data_rate: DataRate) -> Option<(Self, u32)> {
+        if card_clock_hz == 0 {
+            return None;
+        }
+        let prescaled = root_clock_hz / data_rate.prescaler();
+        let divisor = prescaled.div_ceil(card_clock_hz).max(1);
+        if divisor > 16 {
+            return None;
+        }
+        let timing = Timing {
+            divisor: divisor as u8,
+            data_rate,
+            data_timeout: DataTimeout::default(),
+        };
+        Some((timing, timing.card_clock_hz(root_clock_hz)))
+    }
+}
+
+/// Data timeout selection.
+///
+/// The peripheral signals [`Status::DTOE`] when a data phase,
+/// or a busy signal, exceeds the timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataTimeout {
+    /// Time out after 2^N SD clock cycles.
+    ///
+    /// The implementation clamps N between 14 and 29.
+    Cycles(u8),
+    /// Never time out.
+    ///
+    /// The driver may still time out if you've set a [`Timeout`].
+    Disabled,
+}
+
+impl DataTimeout {
+    /// The shortest timeout.
+    pub const MIN: Self = Self::Cycles(14);
+    /// The longest timeout, short of disabling the timeout.
+    pub const MAX: Self = Self::Cycles(29);
+
+    /// Compute the shortest timeout that's at least `micros` microseconds.
+    ///
+    /// `sd_clock_hz` is the frequency of the SD clock, after the divisor
+    /// and prescaler. If the duration exceeds the longest timeout, this
+    /// returns [`MAX`](Self::MAX).
+    pub const fn from_micros(sd_clock_hz: u32, micros: u32) -> Self {
+        let cycles = sd_clock_hz as u64 * micros as u64 / 1_000_000;
+        let mut n = 14;
+        while n < 29 && (1u64 << n) < cycles {
+            n += 1;
+        }
+        Self::Cycles(n)
+    }
+
+    /// Returns the DTOCV field value, or `None` if the timeout is disabled.
+    fn dtocv(self) -> Option<u32> {
+        match self {
+            Self::Cycles(n) => Some(n.clamp(14, 29) as u32 - 14),
+            Self::Disabled => None,
+        }
+    }
+}
+
+impl Default for DataTimeout {
+    fn default() -> Self {
+        Self::MIN
+    }
+}
+
+/// Bus speed modes for SD and eMMC cards.
+///
+/// Each mode has a maximum card clock, and a data rate. See
+/// [`set_bus_mode`](Usdhc::set_bus_mode) to select a mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BusMode {
+    /// Card identification, up to 400 KHz.
+    Identification,
+    /// SD default speed, up to 25 MHz.
+    SdDefaultSpeed,
+    /// SD high speed, up to 50 MHz.
+    SdHighSpeed,
+    /// SD UHS-I SDR50, up to 100 MHz.
+    ///
+    /// The card may need tuning at this speed.
+    SdSdr50,
+    /// SD UHS-I SDR104, up to 208 MHz.
+    ///
+    /// The card needs tuning at this speed.
+    SdSdr104,
+    /// SD UHS-I DDR50, up to 50 MHz on both clock edges.
+    SdDdr50,
+    /// eMMC backwards-compatible timing, up to 26 MHz.
+    MmcLegacy,
+    /// eMMC high speed, up to 52 MHz.
+    MmcHighSpeed,
+    /// eMMC high speed DDR, up to 52 MHz on both clock edges.
+    MmcHighSpeedDdr,
+    /// eMMC HS200, up to 200 MHz.
+    ///
+    /// The card needs tuning at this speed.
+    MmcHs200,
+    /// eMMC HS400, up to 200 MHz on both clock edges.
+    ///
+    /// Use [`enable_hs400`](Usdhc::enable_hs400) to select this mode.
+    MmcHs400,
+}
+
+impl BusMode {
+    /// Returns the fastest card clock for this mode.
+    pub const fn max_clock_hz(self) -> u32 {
+        match self {
+            Self::Identification => 400_000,
+            Self::SdDefaultSpeed => 25_000_000,
+            Self::SdHighSpeed | Self::SdDdr50 => 50_000_000,
+            Self::SdSdr50 => 100_000_000,
+            Self::SdSdr104 => 208_000_000,
+            Self::MmcLegacy => 26_000_000,
+            Self::MmcHighSpeed | Self::MmcHighSpeedDdr => 52_000_000,
+            Self::MmcHs200 | Self::MmcHs400 => 200_000_000,
+        }
+    }
+
+    /// Returns `true` if this mode transfers data on both clock edges.
+    pub const fn is_ddr(self) -> bool {
+        matches!(self, Self::SdDdr50 | Self::MmcHighSpeedDdr | Self::MmcHs400)
+    }
+
+    /// Returns `true` if this mode requires tuning before data transfers.
+    pub const fn requires_tuning(self) -> bool {
+        matches!(self, Self::SdSdr104 | Self::MmcHs200)
+    }
+}
+
+/// Host controller capabilities.
+///
+/// See [`capabilities`](Usdhc::capabilities) to read the capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Supports SDR50.
+    pub sdr50: bool,
+    /// Supports SDR104, and HS200.
+    pub sdr104: bool,
+    /// Supports DDR50, and eMMC DDR modes.
+    pub ddr50: bool,
+    /// SDR50 requires tuning.
+    pub sdr50_tuning: bool,
+    /// The largest block, in bytes.
+    pub max_block_length: usize,
+    /// Supports ADMA.
+    pub adma: bool,
+    /// Supports high speed.
+    pub high_speed: bool,
+    /// Supports simple DMA.
+    pub dma: bool,
+    /// Supports suspend and resume.
+    pub suspend_resume: bool,
+    /// Supports 3.3V.
+    pub voltage_3v3: bool,
+    /// Supports 3.0V.
+    pub voltage_3v0: bool,
+    /// Supports 1.8V.
+    pub voltage_1v8: bool,
+}
+
+impl Capabilities {
+    /// Returns `true` if the host supports the bus mode.
+    pub const fn supports(&self, mode: BusMode) -> bool {
+        match mode {
+            BusMode::Identification | BusMode::SdDefaultSpeed | BusMode::MmcLegacy => true,
+            BusMode::SdHighSpeed | BusMode::MmcHighSpeed => self.high_speed,
+            BusMode::MmcHighSpeedDdr => self.high_speed && self.ddr50,
+            BusMode::SdSdr50 => self.sdr50 && self.voltage_1v8,
+            BusMode::SdSdr104 | BusMode::MmcHs200 => self.sdr104 && self.voltage_1v8,
+            BusMode::SdDdr50 => self.ddr50 && self.voltage_1v8,
+            BusMode::MmcHs400 => self.sdr104 && self.ddr50 && self.voltage_1v8,
+        }
+    }
+}
+
+/// An error when changing the bus mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ModeError {
+    /// The root clock can't be divided down to the mode's card clock.
+    ClockUnreachable,
+    /// DDR modes need a 4-bit or 8-bit bus.
+    BusWidth,
+    /// The host doesn't support the mode.
+    NotSupported,
+    /// The driver timed out while changing the mode.
+    Timeout(TimeoutError),
+}
+
+impl From<TimeoutError> for ModeError {
+    fn from(timeout: TimeoutError) -> Self {
+        Self::Timeout(timeout)
+    }
 }
 
 /// Data rate and prescaler selection.
@@ -215,6 +581,16 @@ pub enum DataRate {
     DualDataRate(DDRPrescaler),
 }
 
+impl DataRate {
+    /// Returns how much the prescaler divides the root clock.
+    pub const fn prescaler(self) -> u32 {
+        match self {
+            Self::SingleDataRate(sdr) => sdr.divide(),
+            Self::DualDataRate(ddr) => ddr.divide(),
+        }
+    }
+}
+
 /// Prescaler selections for single data rate mode.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[non_exhaustive]
@@ -224,6 +600,46 @@ pub enum SDRPrescaler {
     ///
     /// This effectively bypasses the prescaler.
     Divide1 = 0,
+    /// Divide by 2.
+    Divide2 = 0x01,
+    /// Divide by 4.
+    Divide4 = 0x02,
+    /// Divide by 8.
+    Divide8 = 0x04,
+    /// Divide by 16.
+    Divide16 = 0x08,
+    /// Divide by 32.
+    Divide32 = 0x10,
+    /// Divide by 64.
+    Divide64 = 0x20,
+    /// Divide by 128.
+    Divide128 = 0x40,
+    /// Divide by 256.
+    Divide256 = 0x80,
+}
+
+impl SDRPrescaler {
+    /// All prescaler selections, from smallest to largest division.
+    pub const ALL: [Self; 9] = [
+        Self::Divide1,
+        Self::Divide2,
+        Self::Divide4,
+        Self::Divide8,
+        Self::Divide16,
+        Self::Divide32,
+        Self::Divide64,
+        Self::Divide128,
+        Self::Divide256,
+    ];
+
+    /// Returns how much this prescaler divides the root clock.
+    pub const fn divide(self) -> u32 {
+        match self {
+            Self::Divide1 => 1,
+            // One-hot encoding, shifted once.
+            _ => (self as u32) << 1,
+        }
+    }
 }
 
 /// Prescaler selections for dual data rate mode.
@@ -233,6 +649,46 @@ pub enum SDRPrescaler {
 pub enum DDRPrescaler {
     /// Divide by 2.
     Divide2 = 0,
+    /// Divide by 4.
+    Divide4 = 0x01,
+    /// Divide by 8.
+    Divide8 = 0x02,
+    /// Divide by 16.
+    Divide16 = 0x04,
+    /// Divide by 32.
+    Divide32 = 0x08,
+    /// Divide by 64.
+    Divide64 = 0x10,
+    /// Divide by 128.
+    Divide128 = 0x20,
+    /// Divide by 256.
+    Divide256 = 0x40,
+    /// Divide by 512.
+    Divide512 = 0x80,
+}
+
+impl DDRPrescaler {
+    /// All prescaler selections, from smallest to largest division.
+    pub const ALL: [Self; 9] = [
+        Self::Divide2,
+        Self::Divide4,
+        Self::Divide8,
+        Self::Divide16,
+        Self::Divide32,
+        Self::Divide64,
+        Self::Divide128,
+        Self::Divide256,
+        Self::Divide512,
+    ];
+
+    /// Returns how much this prescaler divides the root clock.
+    pub const fn divide(self) -> u32 {
+        match self {
+            Self::Divide2 => 2,
+            // One-hot encoding, shifted twice.
+            _ => (self as u32) << 2,
+        }
+    }
 }
 
 bitflags::bitflags! {
@@ -351,6 +807,17 @@ pub enum DataTransferDirection {
 /// This is a lower-level driver on which you can build more advanced, safer functions.
 pub struct Usdhc {
     inst: ral::Instance,
+    dma: Option<DmaSelect>,
+    adma1: Option<&'static mut [adma::Adma1Descriptor]>,
+    adma2: Option<&'static mut [adma::Adma2Descriptor]>,
+    multi_block: MultiBlockMode,
+    timeout: Timeout,
+    root_clock_hz: u32,
+    tuned: Option<tuning::Tuned>,
+    mode: BusMode,
+    ddr_dll: Option<DllConfig>,
+    engine: interrupt::Engine,
+    detector: Option<detect::Detector>,
 }
 
 impl Usdhc {
@@ -368,33 +835,67 @@ impl Usdhc {
     /// The implementation assumes that it is the sole owner of this peripheral.
     /// Caller must also ensure that this object represents all of the software
     /// manipulating this peripheral.
+    ///
+    /// `root_clock_hz` is the frequency of the uSDHC root clock. The driver
+    /// uses this to derive the card clock for each [`BusMode`].
     #[inline]
-    pub unsafe fn new(ptr: *const ()) -> Self {
+    pub unsafe fn new(ptr: *const (), root_clock_hz: u32) -> Self {
         let inst = unsafe { ral::Instance::new(ptr) };
-        Self { inst }
+        Self {
+            inst,
+            dma: None,
+            adma1: None,
+            adma2: None,
+            multi_block: MultiBlockMode::AutoCmd12,
+            timeout: Timeout::Never,
+            root_clock_hz,
+            tuned: None,
+            mode: BusMode::Identification,
+            ddr_dll: None,
+            engine: interrupt::Engine::new(),
+            detector: None,
+        }
     }
 
     /// Issue a full software reset.
     ///
     /// This resets all system control, command, and data states. Blocks
-    /// until the reset completes.
+    /// until the reset completes, or until the [`Timeout`] expires.
     #[inline]
-    pub fn software_reset(&mut self) {
+    pub fn software_reset(&mut self) -> Result<(), TimeoutError> {
         const RESET_DONE: (u32, u32, u32) = (0, 0, 0);
         // Might be overkill to also tickle RSTC and RSTD; RSTA is supposed
         // to affect those functions. But let's be thorough...
         ral::modify_reg!(ral, self.inst, SYS_CTRL, RSTA: 1, RSTC: 1, RSTD: 1);
-        while ral::read_reg!(ral, self.inst, SYS_CTRL, RSTA, RSTC, RSTD) != RESET_DONE {}
+        self.poll(|usdhc| {
+            (ral::read_reg!(ral, usdhc.inst, SYS_CTRL, RSTA, RSTC, RSTD) == RESET_DONE)
+                .then_some(())
+        })
+        .ok_or(TimeoutError::SoftwareReset)
     }
 
     /// Reset the command path and line.
     ///
-    /// This performs a subset of the [`software_reset()`] behavior, just for
-    /// the command circuit. Blocks until the reset completes.
+    /// This performs a subset of the [`software_reset()`](Self::software_reset) behavior, just for
+    /// the command circuit. Blocks until the reset completes, or until the
+    /// [`Timeout`] expires.
     #[inline]
-    pub fn command_reset(&mut self) {
+    pub fn command_reset(&mut self) -> Result<(), TimeoutError> {
         ral::modify_reg!(ral, self.inst, SYS_CTRL, RSTC: 1);
-        while ral::read_reg!(ral, self.inst, SYS_CTRL, RSTC == 1) {}
+        self.poll(|usdhc| ral::read_reg!(ral, usdhc.inst, SYS_CTRL, RSTC == 0).then_some(()))
+            .ok_or(TimeoutError::CommandReset)
+    }
+
+    /// Reset the data path and lines.
+    ///
+    /// This performs a subset of the [`software_reset()`](Self::software_reset)
+    /// behavior, just for the data circuit. Blocks until the reset completes, or
+    /// until the [`Timeout`] expires.
+    #[inline]
+    pub fn data_reset(&mut self) -> Result<(), TimeoutError> {
+        ral::modify_reg!(ral, self.inst, SYS_CTRL, RSTD: 1);
+        self.poll(|usdhc| ral::read_reg!(ral, usdhc.inst, SYS_CTRL, RSTD == 0).then_some(()))
+            .ok_or(TimeoutError::DataReset)
     }
 
     /// Control the hardware reset line.
@@ -413,11 +914,17 @@ impl Usdhc {
 
     /// Set timing parameters.
     ///
-    /// See [`Timing`] documentation for more information. This call blocks while
-    /// until the internal SD clock stabilizes.
+    /// See [`Timing`] documentation for more information. This call blocks
+    /// until the internal SD clock stabilizes, or until the [`Timeout`] expires.
     #[inline]
-    pub fn set_timing(&mut self, timing: Timing) {
-        while !self.present_state().intersects(PresentState::SDSTB) {}
+    pub fn set_timing(&mut self, timing: Timing) -> Result<(), TimeoutError> {
+        self.poll(|usdhc| {
+            usdhc
+                .present_state()
+                .intersects(PresentState::SDSTB)
+                .then_some(())
+        })
+        .ok_or(TimeoutError::ClockStable)?;
 
         let (prescaler, ddr_en) = match timing.data_rate {
             DataRate::DualDataRate(ddr) => (ddr as u32, 1),
@@ -426,14 +933,113 @@ impl Usdhc {
         ral::modify_reg!(ral, self.inst, MIX_CTRL, DDR_EN: ddr_en);
 
         let divisor = timing.divisor.clamp(1, 16) - 1;
+        let dtocv = timing.data_timeout.dtocv();
         ral::modify_reg!(
             ral,
             self.inst,
             SYS_CTRL,
             DVS: divisor as u32,
             SDCLKFS: prescaler,
-            DTOCV: 0 // TODO allow user config through Timing.
+            DTOCV: dtocv.unwrap_or(0)
         );
+        ral::modify_reg!(ral, self.inst, MMC_BOOT, DISABLE_TIME_OUT: dtocv.is_none() as u32);
+        Ok(())
+    }
+
+    /// Select the bus mode.
+    ///
+    /// This sets the fastest card clock for the mode that doesn't exceed the
+    /// mode's maximum, along with the mode's data rate. It also selects the
+    /// fixed sampling clock; [tune](Self::tune) the card before using a mode that
+    /// [requires tuning](BusMode::requires_tuning). The data timeout is
+    /// at least 250ms.
+    ///
+    /// Returns the card clock frequency. This doesn't signal the card; make
+    /// sure that the card supports the mode before calling this. Returns
+    /// [`ModeError::NotSupported`] if the [host doesn't support](Capabilities::supports)
+    /// the mode.
+    ///
+    /// # DDR
+    ///
+    /// Set a 4-bit or 8-bit bus before selecting a [DDR](BusMode::is_ddr) mode;
+    /// otherwise, this returns [`ModeError::BusWidth`]. The DDR prescaler halves
+    /// the clock before the divisor, and the card transfers data on both edges.
+    /// If you've set a [DDR delay line](Self::set_ddr_dll), this enables it
+    /// and waits for it to lock. Other modes disable the delay line.
+    pub fn set_bus_mode(&mut self, mode: BusMode) -> Result<u32, ModeError> {
+        self.reset_tuning();
+        self.apply_bus_mode(mode)
+    }
+
+    /// Set the clock and data rate for the mode, without changing the sampling clock.
+    fn apply_bus_mode(&mut self, mode: BusMode) -> Result<u32, ModeError> {
+        if !self.capabilities().supports(mode) {
+            return Err(ModeError::NotSupported);
+        }
+        if mode.is_ddr() && self.data_transfer_width() == DataTransferWidth::Bit1 {
+            return Err(ModeError::BusWidth);
+        }
+
+        let (mut timing, hz) = if mode.is_ddr() {
+            Timing::ddr(self.root_clock_hz, mode.max_clock_hz())
+        } else {
+            Timing::sdr(self.root_clock_hz, mode.max_clock_hz())
+        }
+        .ok_or(ModeError::ClockUnreachable)?;
+
+        timing.data_timeout = DataTimeout::from_micros(hz, 250_000);
+        self.set_timing(timing)?;
+        ral::modify_reg!(ral, self.inst, MIX_CTRL, HS400_MODE: (mode == BusMode::MmcHs400) as u32);
+
+        // HS400 samples with the strobe, not the delay line.
+        match self.ddr_dll {
+            Some(config) if mode.is_ddr() && mode != BusMode::MmcHs400 => {
+                self.enable_dll(config)?
+            }
+            _ => self.disable_dll(),
+        }
+
+        self.mode = mode;
+        Ok(hz)
+    }
+
+    /// Read the host controller capabilities.
+    pub fn capabilities(&self) -> Capabilities {
+        let cap = ral::read_reg!(ral, self.inst, HOST_CTRL_CAP);
+        let bit = |mask: u32| cap & mask != 0;
+        Capabilities {
+            sdr50: bit(ral::HOST_CTRL_CAP::SDR50_SUPPORT::mask),
+            sdr104: bit(ral::HOST_CTRL_CAP::SDR104_SUPPORT::mask),
+            ddr50: bit(ral::HOST_CTRL_CAP::DDR50_SUPPORT::mask),
+            sdr50_tuning: bit(ral::HOST_CTRL_CAP::USE_TUNING_SDR50::mask),
+            max_block_length: 512
+                << ((cap & ral::HOST_CTRL_CAP::MBL::mask) >> ral::HOST_CTRL_CAP::MBL::offset),
+            adma: bit(ral::HOST_CTRL_CAP::ADMAS::mask),
+            high_speed: bit(ral::HOST_CTRL_CAP::HSS::mask),
+            dma: bit(ral::HOST_CTRL_CAP::DMAS::mask),
+            suspend_resume: bit(ral::HOST_CTRL_CAP::SRS::mask),
+            voltage_3v3: bit(ral::HOST_CTRL_CAP::VS33::mask),
+            voltage_3v0: bit(ral::HOST_CTRL_CAP::VS30::mask),
+            voltage_1v8: bit(ral::HOST_CTRL_CAP::VS18::mask),
+        }
+    }
+
+    /// Returns the bus mode.
+    ///
+    /// This is the mode last selected with [`set_bus_mode`](Self::set_bus_mode),
+    /// or by the blocking transport.
+    #[inline]
+    pub fn bus_mode(&self) -> BusMode {
+        self.mode
+    }
+
+    /// Use this delay line configuration for DDR modes.
+    ///
+    /// The next [`set_bus_mode`](Self::set_bus_mode) applies the configuration.
+    /// `None` disables the delay line in DDR modes.
+    #[inline]
+    pub fn set_ddr_dll(&mut self, config: Option<DllConfig>) {
+        self.ddr_dll = config;
     }
 
     /// Read the status flags.
@@ -454,6 +1060,14 @@ impl Usdhc {
         ral::write_reg!(ral, self.inst, INT_STATUS, status.bits());
     }
 
+    /// Read the auto CMD12 error flags.
+    ///
+    /// These flags are only meaningful when [`Status::AC12E`] is set.
+    #[inline]
+    pub fn auto_cmd12_error(&self) -> AutoCmd12Error {
+        AutoCmd12Error::from_bits_truncate(ral::read_reg!(ral, self.inst, AUTOCMD12_ERR_STATUS))
+    }
+
     /// Set the conditions that are signaled through status flags.
     ///
     /// Set bits indicate that the status could be signaled. Clear bits are
@@ -472,12 +1086,28 @@ impl Usdhc {
         ral::write_reg!(ral, self.inst, INT_SIGNAL_EN, status.bits());
     }
 
+    /// Returns the status conditions that trigger an interrupt.
+    #[inline]
+    pub fn status_interrupt(&self) -> Status {
+        Status::from_bits_truncate(ral::read_reg!(ral, self.inst, INT_SIGNAL_EN))
+    }
+
     /// Enable or disable DMA support.
     ///
     /// `None` disables DMA. A `Some(...)` enables DMA using the provided
     /// selection.
+    ///
+    /// The blocking transport remembers this selection, and uses it for data
+    /// transfers. The selection persists across power cycles. When DMA is enabled,
+    /// the transport still falls back to CPU copies for any buffer that isn't
+    /// word aligned.
+    ///
+    /// The peripheral's DMA does not maintain caches. If your buffers are in
+    /// cacheable memory, clean the cache before writing to a card, and invalidate
+    /// the cache after reading from a card.
     #[inline]
     pub fn set_dma_enable(&mut self, dma_enable: Option<DmaSelect>) {
+        self.dma = dma_enable;
         match dma_enable {
             None => {
                 ral::modify_reg!(ral, self.inst, MIX_CTRL, DMAEN: 0);
@@ -490,6 +1120,20 @@ impl Usdhc {
         }
     }
 
+    /// Set how the blocking transport ends multi-block transfers.
+    ///
+    /// By default, the peripheral sends an automatic CMD12 after the last block.
+    /// Use [`MultiBlockMode::AutoCmd23`] for closed-ended transfers, which eMMC
+    /// devices and UHS SD cards prefer.
+    ///
+    /// When you use CMD23 flags, the peripheral shares the simple DMA address
+    /// register with the CMD23 argument. Transfers with flags cannot use simple DMA;
+    /// they use ADMA or CPU copies.
+    #[inline]
+    pub fn set_multi_block_mode(&mut self, mode: MultiBlockMode) {
+        self.multi_block = mode;
+    }
+
     /// Returns the endian mode.
     pub fn endian_mode(&self) -> EndianMode {
         let emode = ral::read_reg!(ral, self.inst, PROT_CTRL, EMODE);
@@ -571,6 +1215,14 @@ impl Usdhc {
         PresentState::from_bits_truncate(ral::read_reg!(ral, self.inst, PRES_STATE))
     }
 
+    /// Read the DAT line signal levels.
+    ///
+    /// Bit N reflects the level of `DAT[N]`.
+    #[inline]
+    pub fn data_line_levels(&self) -> u8 {
+        ral::read_reg!(ral, self.inst, PRES_STATE, DLSL) as u8
+    }
+
     /// Write to the data buffer.
     ///
     /// This performs no checks for available space in the data
@@ -19,8 +19,12 @@
 )]
 
 pub use ral_registers::{modify_reg, read_reg, write_reg};
+#[cfg(not(feature = "sim"))]
 use ral_registers::{RORegister, RWRegister};
 
+#[cfg(feature = "sim")]
+use crate::sim::{RORegister, RWRegister};
+
 #[doc = "uSDHC"]
 #[repr(C)]
 pub(crate) struct RegisterBlock {
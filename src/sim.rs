@@ -0,0 +1,1519 @@
+//! A software model of the uSDHC peripheral.
+//!
+//! The [`Simulator`] behaves like a uSDHC register block with a virtual
+//! [`Card`] attached to its bus. Point a [`Usdhc`](crate::Usdhc) at the
+//! simulator, and you can exercise the driver, and the blocking transport,
+//! on your development machine.
+//!
+//! ```
+//! use imxrt_usdhc::{sim::{MemoryCard, Simulator}, Status, Usdhc};
+//!
+//! let sim = Simulator::new(MemoryCard::new(1024));
+//! // Safety: the simulator outlives the driver.
+//! let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), 198_000_000) };
+//! usdhc.software_reset().unwrap();
+//! usdhc.set_status_enable(Status::all());
+//! ```
+//!
+//! # Model
+//!
+//! The simulator completes every command and data phase as soon as software
+//! asks for it. It models
+//!
+//! - write-1-to-clear status flags, gated by the status enable register.
+//! - the command and data inhibit flags, buffer enable flags, and card
+//!   presence flags of the present state.
+//! - the data buffer, including the read and write watermark levels.
+//! - the command response registers.
+//! - block counting, and automatic CMD12 and CMD23 for multi-block transfers.
+//! - software resets.
+//! - card detection through the card detect switch, DAT3, or the test level.
+//!   DAT3 detection corrupts data transfers on a 4-bit or 8-bit bus.
+//! - host capabilities.
+//! - delay line and strobe delay line locking.
+//! - standard and manual tuning, and re-tuning requests. Sampling passes for
+//!   taps in [`PASSING_TAPS`].
+//!
+//! The simulator performs simple DMA, ADMA1, and ADMA2 within memory that you
+//! provide. See [`set_dma_memory`](Simulator::set_dma_memory) for more
+//! information. It assumes little endian data buffer access.
+//!
+//! # Feature
+//!
+//! The simulator requires the `"sim"` feature. This feature replaces the
+//! register access layer with one that can only access a simulator. Don't
+//! enable this feature in firmware.
+
+use core::cell::{Cell, RefCell, RefMut};
+use std::boxed::Box;
+use std::vec;
+use std::vec::Vec;
+
+use crate::{ral, Status};
+
+/// A register that's backed by a simulator.
+///
+/// The simulator observes every read and write of the register block.
+#[repr(transparent)]
+pub(crate) struct Register<T>(Cell<T>);
+
+pub(crate) type RWRegister<T> = Register<T>;
+pub(crate) type RORegister<T> = Register<T>;
+
+/// The alignment of the simulator.
+///
+/// The register block fits within this alignment. A register uses the alignment
+/// to find its simulator.
+const ALIGNMENT: usize = 4096;
+
+impl Register<u32> {
+    #[inline]
+    pub(crate) fn read(&self) -> u32 {
+        // Safety: the register is part of a simulator. See the alignment
+        // requirements on the simulator.
+        unsafe { self.access(None) }
+    }
+
+    #[inline]
+    pub(crate) fn write(&self, value: u32) {
+        // Safety: see read.
+        unsafe { self.access(Some(value)) };
+    }
+
+    /// Read the register value without notifying the simulator.
+    fn get(&self) -> u32 {
+        self.0.get()
+    }
+
+    /// Write the register value without notifying the simulator.
+    fn set(&self, value: u32) {
+        self.0.set(value)
+    }
+
+    unsafe fn access(&self, write: Option<u32>) -> u32 {
+        let address = self as *const Self as usize;
+        let base = address & !(ALIGNMENT - 1);
+        // Safety: the header is the first member of a simulator, and the
+        // simulator is aligned such that the base of the simulator can be
+        // computed from any register address.
+        let header = unsafe { &*(base as *const Header) };
+        unsafe { (header.access)(base as *const (), address - base, write) }
+    }
+}
+
+/// A register access handler.
+///
+/// Accepts the base address of the simulator, the register offset,
+/// and a value (if writing). Returns the value read from the register.
+type Access = unsafe fn(*const (), usize, Option<u32>) -> u32;
+
+#[repr(C)]
+struct Header {
+    registers: ral::RegisterBlock,
+    access: Access,
+}
+
+/// Register offsets, used to dispatch register accesses.
+mod offset {
+    use crate::ral::RegisterBlock;
+    use core::mem::offset_of;
+
+    pub const DS_ADDR: usize = offset_of!(RegisterBlock, DS_ADDR);
+    pub const CMD_XFR_TYP: usize = offset_of!(RegisterBlock, CMD_XFR_TYP);
+    pub const MIX_CTRL: usize = offset_of!(RegisterBlock, MIX_CTRL);
+    pub const DATA_BUFF_ACC_PORT: usize = offset_of!(RegisterBlock, DATA_BUFF_ACC_PORT);
+    pub const PRES_STATE: usize = offset_of!(RegisterBlock, PRES_STATE);
+    pub const PROT_CTRL: usize = offset_of!(RegisterBlock, PROT_CTRL);
+    pub const SYS_CTRL: usize = offset_of!(RegisterBlock, SYS_CTRL);
+    pub const INT_STATUS: usize = offset_of!(RegisterBlock, INT_STATUS);
+    pub const CMD_RSP0: usize = offset_of!(RegisterBlock, CMD_RSP0);
+    pub const CMD_RSP3: usize = offset_of!(RegisterBlock, CMD_RSP3);
+    pub const ADMA_ERR_STATUS: usize = offset_of!(RegisterBlock, ADMA_ERR_STATUS);
+    pub const DLL_STATUS: usize = offset_of!(RegisterBlock, DLL_STATUS);
+    pub const STROBE_DLL_STATUS: usize = offset_of!(RegisterBlock, STROBE_DLL_STATUS);
+}
+
+/// Extract a field from a register value.
+macro_rules! field {
+    ($value:expr, $reg:ident, $field:ident) => {
+        ($value & ral::$reg::$field::mask) >> ral::$reg::$field::offset
+    };
+}
+
+/// The delay cell taps that sample data correctly.
+///
+/// The simulator uses this range for all tuning.
+pub const PASSING_TAPS: core::ops::Range<u32> = 24..56;
+
+/// The reset value of `HOST_CTRL_CAP`.
+///
+/// See [`set_capabilities`](Simulator::set_capabilities) to change the
+/// capabilities.
+pub const HOST_CTRL_CAP: u32 = 0x07F3_B407;
+
+/// The largest block supported by the peripheral.
+const MAX_BLOCK_SIZE: usize = 4096;
+
+/// The simple DMA pauses when it crosses a multiple of this address.
+const SIMPLE_DMA_BOUNDARY: u32 = 4096;
+
+/// A response from a card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    /// A 48 bit response.
+    ///
+    /// This is the 32 bit payload of the response, without the command
+    /// index, CRC, and end bit.
+    R48(u32),
+    /// A 136 bit response.
+    ///
+    /// The four words hold the 128 bits of the response, least significant
+    /// word first. The least significant byte holds the CRC and end bit.
+    /// The simulator discards that byte, just like the hardware.
+    R136([u32; 4]),
+}
+
+/// An error during a data phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataError {
+    /// The data block had a bad CRC.
+    Crc,
+    /// The card never transferred the block.
+    Timeout,
+    /// The data block had a bad end bit.
+    EndBit,
+}
+
+impl DataError {
+    fn status(self) -> Status {
+        match self {
+            DataError::Crc => Status::DCE,
+            DataError::Timeout => Status::DTOE,
+            DataError::EndBit => Status::DEBE,
+        }
+    }
+}
+
+/// A virtual card attached to the simulator.
+///
+/// The simulator calls these methods when it needs the card to act. Track the
+/// card's state between commands and data phases.
+pub trait Card {
+    /// Handle a command from the host.
+    ///
+    /// `index` and `argument` are the values the host sent. Return `None` if
+    /// the card doesn't respond to the command. If the host expected a
+    /// response, the simulator signals a command timeout.
+    fn command(&mut self, index: u8, argument: u32) -> Option<Response>;
+
+    /// Supply the next block of a read from the card.
+    ///
+    /// `block` is sized for the block size configured in the peripheral.
+    fn read_block(&mut self, block: &mut [u8]) -> Result<(), DataError>;
+
+    /// Accept the next block of a write to the card.
+    ///
+    /// `block` is sized for the block size configured in the peripheral.
+    fn write_block(&mut self, block: &[u8]) -> Result<(), DataError>;
+}
+
+impl<C: Card + ?Sized> Card for Box<C> {
+    fn command(&mut self, index: u8, argument: u32) -> Option<Response> {
+        (**self).command(index, argument)
+    }
+    fn read_block(&mut self, block: &mut [u8]) -> Result<(), DataError> {
+        (**self).read_block(block)
+    }
+    fn write_block(&mut self, block: &[u8]) -> Result<(), DataError> {
+        (**self).write_block(block)
+    }
+}
+
+/// How the internal DMA moves a data phase.
+#[derive(Clone, Copy)]
+enum Dma {
+    /// Simple DMA, starting at the system address.
+    Simple,
+    /// ADMA1, working through the descriptor table at the ADMA system address.
+    Adma1(Segment),
+    /// ADMA2, working through the descriptor table at the ADMA system address.
+    Adma2(Segment),
+}
+
+/// The data that an ADMA descriptor describes.
+#[derive(Clone, Copy, Default)]
+struct Segment {
+    /// The address of the next byte.
+    address: u32,
+    /// The bytes left to move.
+    remaining: usize,
+    /// `true` if this is the last descriptor.
+    end: bool,
+    /// The length of the next ADMA1 transfer, from the last set descriptor.
+    next_length: usize,
+}
+
+/// ADMA error states, for the error status.
+mod adma_state {
+    /// Fetching a descriptor.
+    pub const FETCH: u32 = 1;
+    /// Transferring data.
+    pub const TRANSFER: u32 = 3;
+}
+
+/// An active data phase.
+struct DataPhase {
+    /// `true` if this is a read from the card.
+    read: bool,
+    /// Bytes per block.
+    block_size: usize,
+    /// Number of blocks left to transfer, including the
+    /// active block. `None` means "transfer until stopped."
+    blocks: Option<u32>,
+    /// Byte position in the active block.
+    position: usize,
+    /// Send CMD12 once all blocks transfer.
+    auto_cmd12: bool,
+    /// Decrement BLKCNT with every block.
+    count_blocks: bool,
+    /// The DMA that moves the data, if any.
+    dma: Option<Dma>,
+    buffer: [u8; MAX_BLOCK_SIZE],
+}
+
+/// Simulator state that isn't directly represented in registers.
+struct State {
+    data: Option<DataPhase>,
+    /// Tuning commands sent since tuning started.
+    tuning_commands: u32,
+}
+
+/// A simulated uSDHC peripheral, with a card attached to its bus.
+///
+/// Use [`as_ptr`](Self::as_ptr) to create a [`Usdhc`](crate::Usdhc) driver.
+/// See the [module documentation](self) for more information.
+#[repr(C, align(4096))]
+pub struct Simulator<C> {
+    // Must be the first member; see the register implementation.
+    header: Header,
+    state: RefCell<State>,
+    inserted: Cell<bool>,
+    detect_switch: Cell<bool>,
+    write_protect: Cell<bool>,
+    retune: Cell<bool>,
+    capabilities: Cell<u32>,
+    /// The base and length of the memory that the DMA can access.
+    dma_memory: Cell<Option<(*mut u8, usize)>>,
+    card: RefCell<C>,
+}
+
+impl<C: Card> Simulator<C> {
+    /// Create a simulator with an inserted card.
+    ///
+    /// The simulator starts in its reset state. It's boxed so that its address
+    /// never changes while a driver uses it.
+    pub fn new(card: C) -> Box<Self> {
+        let sim = Box::new(Self {
+            header: Header {
+                // Safety: the register block is a collection of u32 cells and
+                // padding. Zero is valid for all of them.
+                registers: unsafe { core::mem::zeroed() },
+                access: Self::access,
+            },
+            state: RefCell::new(State {
+                data: None,
+                tuning_commands: 0,
+            }),
+            inserted: Cell::new(true),
+            detect_switch: Cell::new(true),
+            write_protect: Cell::new(false),
+            retune: Cell::new(false),
+            capabilities: Cell::new(HOST_CTRL_CAP),
+            dma_memory: Cell::new(None),
+            card: RefCell::new(card),
+        });
+        sim.reset();
+        sim
+    }
+
+    /// Returns the pointer to the simulated register block.
+    ///
+    /// Use this pointer to create a [`Usdhc`](crate::Usdhc). The simulator
+    /// must outlive the driver.
+    pub fn as_ptr(&self) -> *const () {
+        &self.header.registers as *const _ as *const ()
+    }
+
+    /// Access the card.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the card is already borrowed.
+    pub fn card(&self) -> RefMut<'_, C> {
+        self.card.borrow_mut()
+    }
+
+    /// Insert or remove the card.
+    ///
+    /// This updates the card presence flags, and signals card insertion
+    /// or removal. A removed card never responds to commands.
+    pub fn set_card_inserted(&self, inserted: bool) {
+        let detected = self.card_detected();
+        if inserted != self.inserted.replace(inserted) && !inserted {
+            self.state.borrow_mut().data = None;
+        }
+        self.signal_card_detect(detected);
+    }
+
+    /// Connect or disconnect the card detect switch.
+    ///
+    /// By default, the board has a switch. Without the switch, the card
+    /// detect pin never detects a card; use DAT3 detection, or the test level.
+    pub fn set_card_detect_switch(&self, connected: bool) {
+        let detected = self.card_detected();
+        self.detect_switch.set(connected);
+        self.signal_card_detect(detected);
+    }
+
+    /// Returns `true` if the selected card detect signal detects a card.
+    fn card_detected(&self) -> bool {
+        let prot_ctrl = self.header.registers.PROT_CTRL.get();
+        if field!(prot_ctrl, PROT_CTRL, CDSS) != 0 {
+            field!(prot_ctrl, PROT_CTRL, CDTL) != 0
+        } else if field!(prot_ctrl, PROT_CTRL, D3CD) != 0 {
+            self.inserted.get()
+        } else {
+            self.inserted.get() && self.detect_switch.get()
+        }
+    }
+
+    /// Signal card insertion or removal if detection changed.
+    fn signal_card_detect(&self, detected: bool) {
+        match (detected, self.card_detected()) {
+            (false, true) => self.signal(Status::CINS),
+            (true, false) => self.signal(Status::CRM),
+            _ => {}
+        }
+    }
+
+    /// Set the level of the write protect switch.
+    pub fn set_write_protect(&self, write_protect: bool) {
+        self.write_protect.set(write_protect);
+    }
+
+    /// Set the raw `HOST_CTRL_CAP` value.
+    ///
+    /// By default, the simulator reports the peripheral's reset value,
+    /// which supports all modes. The value survives a software reset.
+    pub fn set_capabilities(&self, capabilities: u32) {
+        self.capabilities.set(capabilities);
+        self.header.registers.HOST_CTRL_CAP.set(capabilities);
+    }
+
+    /// Let the DMA access `length` bytes of memory at `memory`.
+    ///
+    /// The peripheral's DMA addresses are 32 bits wide. The simulator finds those
+    /// addresses in the 4 GiB region that holds `memory`. A DMA access that's
+    /// outside of this memory signals a DMA error. Without DMA memory, every
+    /// DMA transfer signals a DMA error. Place ADMA descriptor tables in DMA
+    /// memory, too.
+    ///
+    /// Simple DMA pauses each time it crosses a 4 KiB boundary, and resumes
+    /// once software writes the next address.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid for reads and writes for as long as the
+    /// simulator may perform DMA.
+    pub unsafe fn set_dma_memory(&self, memory: *mut u8, length: usize) {
+        self.dma_memory.set(Some((memory, length)));
+    }
+
+    /// Request re-tuning, as if the re-tuning timer expired.
+    ///
+    /// The request stays active until software starts tuning.
+    pub fn request_retuning(&self) {
+        self.retune.set(true);
+        self.signal(Status::RTE);
+    }
+
+    /// Returns `true` if the peripheral is signaling an interrupt.
+    pub fn interrupt_pending(&self) -> bool {
+        let regs = &self.header.registers;
+        regs.INT_STATUS.get() & regs.INT_SIGNAL_EN.get() != 0
+    }
+
+    unsafe fn access(base: *const (), offset: usize, write: Option<u32>) -> u32 {
+        // Safety: base of the simulator is provided by a register
+        // that's part of this simulator.
+        let sim = unsafe { &*(base as *const Self) };
+        match write {
+            Some(value) => {
+                sim.write(offset, value);
+                0
+            }
+            None => sim.read(offset),
+        }
+    }
+
+    /// Returns the register at the given offset.
+    fn register(&self, offset: usize) -> &Register<u32> {
+        debug_assert!(offset % 4 == 0 && offset < core::mem::size_of::<ral::RegisterBlock>());
+        // Safety: offset is within the register block, and it's aligned for
+        // a register. Padding is zeroed, so any reserved word is a valid u32.
+        unsafe {
+            &*((&self.header.registers as *const ral::RegisterBlock as *const u8).add(offset)
+                as *const Register<u32>)
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        match offset {
+            offset::PRES_STATE => self.present_state(),
+            offset::DLL_STATUS => self.dll_status(),
+            offset::STROBE_DLL_STATUS => self.strobe_dll_status(),
+            offset::DATA_BUFF_ACC_PORT => self.read_data_buffer(),
+            _ => self.register(offset).get(),
+        }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        let regs = &self.header.registers;
+        match offset {
+            // Read-only registers.
+            offset::CMD_RSP0..=offset::CMD_RSP3
+            | offset::PRES_STATE
+            | offset::ADMA_ERR_STATUS
+            | offset::DLL_STATUS
+            | offset::STROBE_DLL_STATUS => {}
+            offset::INT_STATUS => regs.INT_STATUS.set(regs.INT_STATUS.get() & !value),
+            offset::SYS_CTRL => self.write_system_control(value),
+            offset::DATA_BUFF_ACC_PORT => self.write_data_buffer(value),
+            offset::MIX_CTRL => {
+                if field!(value & !regs.MIX_CTRL.get(), MIX_CTRL, EXE_TUNE) != 0 {
+                    self.state.borrow_mut().tuning_commands = 0;
+                    self.retune.set(false);
+                }
+                regs.MIX_CTRL.set(value);
+            }
+            offset::CMD_XFR_TYP => {
+                regs.CMD_XFR_TYP.set(value);
+                self.execute_command(value);
+            }
+            offset::DS_ADDR => {
+                regs.DS_ADDR.set(value);
+                // A data phase that's waiting here is a paused simple DMA.
+                let paused = {
+                    let mut state = self.state.borrow_mut();
+                    match state.data {
+                        Some(DataPhase { dma: Some(_), .. }) => state.data.take(),
+                        _ => None,
+                    }
+                };
+                if let Some(data) = paused {
+                    self.run_dma(data);
+                }
+            }
+            offset::PROT_CTRL => {
+                let detected = self.card_detected();
+                regs.PROT_CTRL.set(value);
+                self.signal_card_detect(detected);
+            }
+            _ => self.register(offset).set(value),
+        }
+    }
+
+    /// Put all registers into their reset state.
+    fn reset(&self) {
+        let regs = &self.header.registers;
+        for offset in (0..core::mem::size_of::<ral::RegisterBlock>()).step_by(4) {
+            self.register(offset).set(0);
+        }
+        regs.PROT_CTRL.set(0x0880_0020);
+        regs.SYS_CTRL.set(0x0080_800F);
+        regs.HOST_CTRL_CAP.set(self.capabilities.get());
+        regs.WTMK_LVL.set(0x0810_0810);
+        regs.MIX_CTRL.set(0x8000_0000);
+        regs.VEND_SPEC.set(0x2000_7809);
+        regs.TUNING_CTRL.set(0x0021_2800);
+        regs.VEND_SPEC2.set(ral::VEND_SPEC2::ACMD23_ARGU2_EN::mask);
+        self.state.borrow_mut().data = None;
+    }
+
+    /// Set status flags, if they're enabled.
+    fn signal(&self, status: Status) {
+        let regs = &self.header.registers;
+        let enabled = status.bits() & regs.INT_STATUS_EN.get();
+        regs.INT_STATUS.set(regs.INT_STATUS.get() | enabled);
+    }
+
+    fn present_state(&self) -> u32 {
+        let mut pres_state = ral::PRES_STATE::SDSTB::mask
+            | ral::PRES_STATE::CLSL::mask
+            | ral::PRES_STATE::DLSL::mask;
+        if self.inserted.get() && self.detect_switch.get() {
+            pres_state |= ral::PRES_STATE::CDPL::mask;
+        }
+        if self.card_detected() {
+            pres_state |= ral::PRES_STATE::CINST::mask;
+        }
+        if self.write_protect.get() {
+            pres_state |= ral::PRES_STATE::WPSPL::mask;
+        }
+        if self.retune.get() {
+            pres_state |= ral::PRES_STATE::RTR::mask;
+        }
+        let forced_on = field!(
+            self.header.registers.VEND_SPEC.get(),
+            VEND_SPEC,
+            FRC_SDCLK_ON
+        ) != 0;
+        let state = self.state.borrow();
+        if state.data.is_none() && !forced_on {
+            pres_state |= ral::PRES_STATE::SDOFF::mask;
+        }
+        if let Some(data) = &state.data {
+            pres_state |= ral::PRES_STATE::CDIHB::mask | ral::PRES_STATE::DLA::mask;
+            let cpu_ready = data.dma.is_none() && self.buffer_ready(data);
+            if data.read {
+                pres_state |= ral::PRES_STATE::RTA::mask;
+                if cpu_ready {
+                    pres_state |= ral::PRES_STATE::BREN::mask;
+                }
+            } else {
+                pres_state |= ral::PRES_STATE::WTA::mask;
+                if cpu_ready {
+                    pres_state |= ral::PRES_STATE::BWEN::mask;
+                }
+            }
+        }
+        pres_state
+    }
+
+    /// An enabled delay line locks right away, selecting its target.
+    fn dll_status(&self) -> u32 {
+        let dll_ctrl = self.header.registers.DLL_CTRL.get();
+        if field!(dll_ctrl, DLL_CTRL, DLL_CTRL_ENABLE) == 0 {
+            return 0;
+        }
+        let target = field!(dll_ctrl, DLL_CTRL, DLL_CTRL_SLV_DLY_TARGET0)
+            | field!(dll_ctrl, DLL_CTRL, DLL_CTRL_SLV_DLY_TARGET1) << 4;
+        ral::DLL_STATUS::DLL_STS_SLV_LOCK::mask
+            | ral::DLL_STATUS::DLL_STS_REF_LOCK::mask
+            | target << ral::DLL_STATUS::DLL_STS_SLV_SEL::offset
+    }
+
+    /// An enabled strobe delay line locks right away, selecting its target.
+    fn strobe_dll_status(&self) -> u32 {
+        let ctrl = self.header.registers.STROBE_DLL_CTRL.get();
+        if field!(ctrl, STROBE_DLL_CTRL, STROBE_DLL_CTRL_ENABLE) == 0 {
+            return 0;
+        }
+        let target = field!(ctrl, STROBE_DLL_CTRL, STROBE_DLL_CTRL_SLV_DLY_TARGET);
+        ral::STROBE_DLL_STATUS::STROBE_DLL_STS_SLV_LOCK::mask
+            | ral::STROBE_DLL_STATUS::STROBE_DLL_STS_REF_LOCK::mask
+            | target << ral::STROBE_DLL_STATUS::STROBE_DLL_STS_SLV_SEL::offset
+    }
+
+    /// Returns `true` if there's enough data (read) or space (write) in the
+    /// buffer to meet the watermark level.
+    ///
+    /// The card drains the buffer as soon as software writes a word, so there's
+    /// always space for writing.
+    fn buffer_ready(&self, data: &DataPhase) -> bool {
+        let words = (data.block_size - data.position) / 4;
+        if !data.read {
+            return words > 0;
+        }
+        let level = field!(self.header.registers.WTMK_LVL.get(), WTMK_LVL, RD_WML);
+        words > 0 && words >= (level as usize).clamp(1, data.block_size / 4)
+    }
+
+    fn write_system_control(&self, value: u32) {
+        let regs = &self.header.registers;
+        if field!(value, SYS_CTRL, RSTA) != 0 {
+            self.reset();
+            return;
+        }
+        if field!(value, SYS_CTRL, RSTD) != 0 {
+            self.state.borrow_mut().data = None;
+        }
+        let self_clearing = ral::SYS_CTRL::RSTA::mask
+            | ral::SYS_CTRL::RSTC::mask
+            | ral::SYS_CTRL::RSTD::mask
+            | ral::SYS_CTRL::RSTT::mask
+            | ral::SYS_CTRL::INITA::mask;
+        regs.SYS_CTRL.set(value & !self_clearing);
+    }
+
+    fn execute_command(&self, cmd_xfr_typ: u32) {
+        let regs = &self.header.registers;
+        let index = field!(cmd_xfr_typ, CMD_XFR_TYP, CMDINX) as u8;
+        let rsptyp = field!(cmd_xfr_typ, CMD_XFR_TYP, RSPTYP);
+        let dpsel = field!(cmd_xfr_typ, CMD_XFR_TYP, DPSEL) != 0;
+        let abort = field!(cmd_xfr_typ, CMD_XFR_TYP, CMDTYP) == 3;
+
+        if abort {
+            self.state.borrow_mut().data = None;
+        }
+
+        let mix_ctrl = regs.MIX_CTRL.get();
+        let auto_cmd23 = dpsel
+            && field!(mix_ctrl, MIX_CTRL, MSBSEL) != 0
+            && field!(mix_ctrl, MIX_CTRL, AC23EN) != 0;
+        if auto_cmd23 && !self.send_auto_cmd23() {
+            self.signal(Status::AC12E);
+            return;
+        }
+
+        let response = if self.inserted.get() {
+            self.card.borrow_mut().command(index, regs.CMD_ARG.get())
+        } else {
+            None
+        };
+
+        match (rsptyp, response) {
+            (0, _) => {}
+            (_, None) => {
+                self.signal(Status::CTOE);
+                return;
+            }
+            (_, Some(Response::R48(rsp))) => regs.CMD_RSP0.set(rsp),
+            (_, Some(Response::R136(rsp))) => {
+                regs.CMD_RSP0.set(rsp[0] >> 8 | rsp[1] << 24);
+                regs.CMD_RSP1.set(rsp[1] >> 8 | rsp[2] << 24);
+                regs.CMD_RSP2.set(rsp[2] >> 8 | rsp[3] << 24);
+                regs.CMD_RSP3.set(rsp[3] >> 8);
+            }
+        }
+        self.signal(Status::CC);
+
+        // DAT3 detection corrupts data on a wide bus.
+        let prot_ctrl = regs.PROT_CTRL.get();
+        if dpsel
+            && field!(prot_ctrl, PROT_CTRL, D3CD) != 0
+            && field!(prot_ctrl, PROT_CTRL, DTW) != 0
+        {
+            self.signal(Status::DCE);
+            return;
+        }
+
+        let tuning = field!(mix_ctrl, MIX_CTRL, EXE_TUNE) != 0;
+        let standard = field!(regs.TUNING_CTRL.get(), TUNING_CTRL, STD_TUNING_EN) != 0;
+        if dpsel && tuning && standard {
+            self.tuning_step();
+        } else if dpsel && tuning {
+            let tap = field!(
+                regs.CLK_TUNE_CTRL_STATUS.get(),
+                CLK_TUNE_CTRL_STATUS,
+                DLY_CELL_SET_PRE
+            );
+            self.signal(if PASSING_TAPS.contains(&tap) {
+                Status::BRR
+            } else {
+                Status::DCE
+            });
+        } else if dpsel {
+            self.start_data_phase();
+        } else if rsptyp == 3 {
+            // The card releases DAT0 right away.
+            self.signal(Status::TC);
+        }
+    }
+
+    /// Test the next tap with a tuning block.
+    ///
+    /// Tuning finishes once enough consecutive taps pass, or fails once the
+    /// counter expires.
+    fn tuning_step(&self) {
+        let regs = &self.header.registers;
+        let tuning_ctrl = regs.TUNING_CTRL.get();
+        let start = field!(tuning_ctrl, TUNING_CTRL, TUNING_START_TAP);
+        let step = field!(tuning_ctrl, TUNING_CTRL, TUNING_STEP).max(1);
+        let window = field!(tuning_ctrl, TUNING_CTRL, TUNING_WINDOW).max(1);
+        let counter = field!(tuning_ctrl, TUNING_CTRL, TUNING_COUNTER);
+
+        let commands = {
+            let mut state = self.state.borrow_mut();
+            state.tuning_commands += 1;
+            state.tuning_commands
+        };
+        let tap = start + (commands - 1) * step;
+        self.signal(Status::BRR);
+
+        // The last `window` taps, including this one, all pass.
+        let passed = (0..window)
+            .all(|n| n < commands && PASSING_TAPS.contains(&(tap.wrapping_sub(n * step))));
+        let mix_ctrl = regs.MIX_CTRL.get();
+        if passed {
+            let center = tap - (window - 1) * step / 2;
+            regs.CLK_TUNE_CTRL_STATUS
+                .set(center << ral::CLK_TUNE_CTRL_STATUS::TAP_SEL_PRE::offset);
+            regs.MIX_CTRL.set(mix_ctrl & !ral::MIX_CTRL::EXE_TUNE::mask);
+        } else if commands >= counter {
+            regs.MIX_CTRL.set(
+                mix_ctrl & !(ral::MIX_CTRL::EXE_TUNE::mask | ral::MIX_CTRL::SMP_CLK_SEL::mask),
+            );
+        }
+    }
+
+    /// Send CMD23 ahead of a multi-block transfer. Returns `false` if the card
+    /// didn't respond.
+    fn send_auto_cmd23(&self) -> bool {
+        let regs = &self.header.registers;
+        let argument = if field!(regs.VEND_SPEC2.get(), VEND_SPEC2, ACMD23_ARGU2_EN) != 0 {
+            regs.DS_ADDR.get()
+        } else {
+            field!(regs.BLK_ATT.get(), BLK_ATT, BLKCNT)
+        };
+        if self.inserted.get() && self.card.borrow_mut().command(23, argument).is_some() {
+            return true;
+        }
+        regs.AUTOCMD12_ERR_STATUS
+            .set(regs.AUTOCMD12_ERR_STATUS.get() | ral::AUTOCMD12_ERR_STATUS::CNIBAC12E::mask);
+        false
+    }
+
+    fn start_data_phase(&self) {
+        let regs = &self.header.registers;
+        let mix_ctrl = regs.MIX_CTRL.get();
+        let blk_att = regs.BLK_ATT.get();
+
+        let dma = if field!(mix_ctrl, MIX_CTRL, DMAEN) == 0 {
+            None
+        } else {
+            match field!(regs.PROT_CTRL.get(), PROT_CTRL, DMASEL) {
+                0 => Some(Dma::Simple),
+                1 => Some(Dma::Adma1(Segment::default())),
+                2 => Some(Dma::Adma2(Segment::default())),
+                _ => {
+                    self.signal(Status::DMAE);
+                    return;
+                }
+            }
+        };
+
+        let block_size = field!(blk_att, BLK_ATT, BLKSIZE) as usize;
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE || block_size % 4 != 0 {
+            self.signal(Status::DTOE);
+            return;
+        }
+
+        let multi = field!(mix_ctrl, MIX_CTRL, MSBSEL) != 0;
+        let count_blocks = field!(mix_ctrl, MIX_CTRL, BCEN) != 0;
+        let blocks = match (multi, count_blocks) {
+            (false, _) => Some(1),
+            (true, true) => Some(field!(blk_att, BLK_ATT, BLKCNT)),
+            (true, false) => None,
+        };
+        if blocks == Some(0) {
+            self.signal(Status::TC);
+            return;
+        }
+
+        let mut data = DataPhase {
+            read: field!(mix_ctrl, MIX_CTRL, DTDSEL) != 0,
+            block_size,
+            blocks,
+            position: 0,
+            auto_cmd12: multi && field!(mix_ctrl, MIX_CTRL, AC12EN) != 0,
+            count_blocks: multi && count_blocks,
+            dma,
+            buffer: [0; MAX_BLOCK_SIZE],
+        };
+
+        if data.dma.is_some() {
+            self.run_dma(data);
+            return;
+        }
+
+        if data.read {
+            if let Err(err) = self.load_block(&mut data) {
+                self.signal(err.status());
+                return;
+            }
+            self.signal(Status::BRR);
+        } else {
+            self.signal(Status::BWR);
+        }
+        self.state.borrow_mut().data = Some(data);
+    }
+
+    /// Move blocks between the card and memory until the transfer completes,
+    /// or until the simple DMA pauses at a boundary.
+    fn run_dma(&self, mut data: DataPhase) {
+        let regs = &self.header.registers;
+        loop {
+            let start = regs.DS_ADDR.get();
+            let moved = if data.read {
+                self.load_block(&mut data)
+                    .map_err(DataError::status)
+                    .and_then(|()| self.dma_block(&mut data))
+            } else {
+                self.dma_block(&mut data).and_then(|()| {
+                    self.card
+                        .borrow_mut()
+                        .write_block(&data.buffer[..data.block_size])
+                        .map_err(DataError::status)
+                })
+            };
+            if let Err(status) = moved {
+                self.signal(status);
+                return;
+            }
+
+            if !self.complete_block(&mut data) {
+                // The descriptors must describe exactly the transfer.
+                if let Some(Dma::Adma1(segment) | Dma::Adma2(segment)) = data.dma {
+                    if segment.remaining != 0 || !segment.end {
+                        self.signal(self.adma_error(adma_state::TRANSFER, true, false));
+                        return;
+                    }
+                }
+                self.signal(Status::DINT);
+                self.complete_transfer(data.auto_cmd12);
+                return;
+            }
+
+            let next = regs.DS_ADDR.get();
+            let simple = matches!(data.dma, Some(Dma::Simple));
+            if simple && start / SIMPLE_DMA_BOUNDARY != next / SIMPLE_DMA_BOUNDARY {
+                self.state.borrow_mut().data = Some(data);
+                self.signal(Status::DINT);
+                return;
+            }
+        }
+    }
+
+    /// Move the active block between the data buffer and memory.
+    ///
+    /// Returns the status to signal if the DMA fails.
+    fn dma_block(&self, data: &mut DataPhase) -> Result<(), Status> {
+        let mut position = 0;
+        while position < data.block_size {
+            let (address, length) = self.next_segment(data, data.block_size - position)?;
+            let memory = match (self.dma_memory(address, length), data.dma) {
+                (Some(memory), _) => memory,
+                (None, Some(Dma::Simple)) => return Err(Status::DMAE),
+                (None, _) => return Err(self.adma_error(adma_state::TRANSFER, false, false)),
+            };
+            let buffer = &mut data.buffer[position..position + length];
+            // Safety: dma_memory checked that the range is in DMA memory. The
+            // user guarantees that DMA memory is valid.
+            unsafe {
+                if data.read {
+                    core::ptr::copy_nonoverlapping(buffer.as_ptr(), memory, length);
+                } else {
+                    core::ptr::copy_nonoverlapping(memory, buffer.as_mut_ptr(), length);
+                }
+            }
+            position += length;
+        }
+        Ok(())
+    }
+
+    /// Returns the address and length of the DMA's next memory segment, up to
+    /// `wanted` bytes, and advances the DMA past that segment.
+    fn next_segment(&self, data: &mut DataPhase, wanted: usize) -> Result<(u32, usize), Status> {
+        let regs = &self.header.registers;
+        let segment = match data.dma.as_mut() {
+            None => return Err(Status::DMAE),
+            Some(Dma::Simple) => {
+                let address = regs.DS_ADDR.get();
+                regs.DS_ADDR.set(address.wrapping_add(wanted as u32));
+                return Ok((address, wanted));
+            }
+            Some(Dma::Adma1(segment)) => {
+                while segment.remaining == 0 {
+                    if segment.end {
+                        return Err(self.adma_error(adma_state::TRANSFER, true, false));
+                    }
+                    *segment = self.fetch_adma1(segment.next_length)?;
+                }
+                segment
+            }
+            Some(Dma::Adma2(segment)) => {
+                while segment.remaining == 0 {
+                    if segment.end {
+                        return Err(self.adma_error(adma_state::TRANSFER, true, false));
+                    }
+                    *segment = self.fetch_adma2()?;
+                }
+                segment
+            }
+        };
+
+        let length = segment.remaining.min(wanted);
+        let address = segment.address;
+        segment.address = address.wrapping_add(length as u32);
+        segment.remaining -= length;
+        Ok((address, length))
+    }
+
+    /// Fetch the next ADMA1 transfer descriptor.
+    ///
+    /// The ADMA system address points to the next descriptor. `length` is the
+    /// transfer length from the last set descriptor.
+    fn fetch_adma1(&self, mut length: usize) -> Result<Segment, Status> {
+        let regs = &self.header.registers;
+        loop {
+            let address = regs.ADMA_SYS_ADDR.get();
+            let memory = self
+                .dma_memory(address, 4)
+                .ok_or_else(|| self.adma_error(adma_state::FETCH, false, false))?;
+            // Safety: the descriptor is in DMA memory.
+            let descriptor = unsafe { memory.cast::<u32>().read_unaligned() };
+
+            let valid = descriptor & 1 != 0;
+            if !valid {
+                return Err(self.adma_error(adma_state::FETCH, false, true));
+            }
+            let end = descriptor & 1 << 1 != 0;
+            let page = descriptor & !0xFFF;
+            regs.ADMA_SYS_ADDR.set(address.wrapping_add(4));
+            match descriptor >> 4 & 0b11 {
+                // Transfer data from a page.
+                0b10 => {
+                    return Ok(Segment {
+                        address: page,
+                        remaining: length,
+                        end,
+                        next_length: length,
+                    });
+                }
+                // Only a transfer descriptor can end the table.
+                _ if end => return Err(self.adma_error(adma_state::TRANSFER, true, false)),
+                // Set the length of the next transfer.
+                0b01 => length = (descriptor >> 12 & 0xFFFF) as usize,
+                // Link to another descriptor.
+                0b11 => regs.ADMA_SYS_ADDR.set(page),
+                // No operation.
+                _ => {}
+            }
+        }
+    }
+
+    /// Fetch the next ADMA2 transfer descriptor.
+    ///
+    /// The ADMA system address points to the next descriptor.
+    fn fetch_adma2(&self) -> Result<Segment, Status> {
+        let regs = &self.header.registers;
+        loop {
+            let address = regs.ADMA_SYS_ADDR.get();
+            let memory = self
+                .dma_memory(address, 8)
+                .ok_or_else(|| self.adma_error(adma_state::FETCH, false, false))?;
+            // Safety: the descriptor is in DMA memory.
+            let [attributes, data] = unsafe { memory.cast::<[u32; 2]>().read_unaligned() };
+
+            let valid = attributes & 1 != 0;
+            if !valid {
+                return Err(self.adma_error(adma_state::FETCH, false, true));
+            }
+            let end = attributes & 1 << 1 != 0;
+            regs.ADMA_SYS_ADDR.set(address.wrapping_add(8));
+            match attributes >> 4 & 0b11 {
+                // Transfer data. A zero length means 65536 bytes.
+                0b10 => {
+                    let length = match attributes >> 16 {
+                        0 => 1 << 16,
+                        length => length as usize,
+                    };
+                    return Ok(Segment {
+                        address: data,
+                        remaining: length,
+                        end,
+                        ..Segment::default()
+                    });
+                }
+                // Link to another descriptor.
+                0b11 => regs.ADMA_SYS_ADDR.set(data),
+                // No operation.
+                _ if end => return Err(self.adma_error(adma_state::TRANSFER, true, false)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Record an ADMA error in the error status. Returns the status to signal.
+    fn adma_error(&self, state: u32, length_mismatch: bool, descriptor_error: bool) -> Status {
+        self.header.registers.ADMA_ERR_STATUS.set(
+            state << ral::ADMA_ERR_STATUS::ADMAES::offset
+                | (length_mismatch as u32) << ral::ADMA_ERR_STATUS::ADMALME::offset
+                | (descriptor_error as u32) << ral::ADMA_ERR_STATUS::ADMADCE::offset,
+        );
+        Status::DMAE
+    }
+
+    /// Returns the host memory for the DMA address range, or `None` if the range
+    /// isn't in DMA memory.
+    fn dma_memory(&self, address: u32, length: usize) -> Option<*mut u8> {
+        let (base, size) = self.dma_memory.get()?;
+        // DMA addresses are the low 32 bits of a host address.
+        let high = base as usize as u64 & !u64::from(u32::MAX);
+        let offset = (high | u64::from(address)).checked_sub(base as usize as u64)?;
+        let offset = usize::try_from(offset).ok()?;
+        (offset.checked_add(length)? <= size).then(|| base.wrapping_add(offset))
+    }
+
+    /// Fetch the next block for a read from the card.
+    fn load_block(&self, data: &mut DataPhase) -> Result<(), DataError> {
+        data.position = 0;
+        self.card
+            .borrow_mut()
+            .read_block(&mut data.buffer[..data.block_size])
+    }
+
+    fn read_data_buffer(&self) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let Some(data) = state.data.as_mut().filter(|data| data.read) else {
+            return 0;
+        };
+
+        let position = data.position;
+        let word = u32::from_le_bytes(data.buffer[position..position + 4].try_into().unwrap());
+        data.position += 4;
+
+        if data.position < data.block_size {
+            if self.buffer_ready(data) {
+                self.signal(Status::BRR);
+            }
+            return word;
+        }
+
+        if self.complete_block(data) {
+            if let Err(err) = self.load_block(data) {
+                state.data = None;
+                drop(state);
+                self.signal(err.status());
+            } else if self.buffer_ready(data) {
+                self.signal(Status::BRR);
+            }
+        } else {
+            let auto_cmd12 = data.auto_cmd12;
+            state.data = None;
+            drop(state);
+            self.complete_transfer(auto_cmd12);
+        }
+        word
+    }
+
+    fn write_data_buffer(&self, word: u32) {
+        let mut state = self.state.borrow_mut();
+        let Some(data) = state.data.as_mut().filter(|data| !data.read) else {
+            return;
+        };
+
+        let position = data.position;
+        data.buffer[position..position + 4].copy_from_slice(&word.to_le_bytes());
+        data.position += 4;
+
+        if data.position < data.block_size {
+            if self.buffer_ready(data) {
+                self.signal(Status::BWR);
+            }
+            return;
+        }
+
+        let result = self
+            .card
+            .borrow_mut()
+            .write_block(&data.buffer[..data.block_size]);
+        if let Err(err) = result {
+            state.data = None;
+            drop(state);
+            self.signal(err.status());
+            return;
+        }
+
+        if self.complete_block(data) {
+            data.position = 0;
+            self.signal(Status::BWR);
+        } else {
+            let auto_cmd12 = data.auto_cmd12;
+            state.data = None;
+            drop(state);
+            self.complete_transfer(auto_cmd12);
+        }
+    }
+
+    /// Account for a transferred block. Returns `true` if there's
+    /// another block to transfer.
+    fn complete_block(&self, data: &mut DataPhase) -> bool {
+        let regs = &self.header.registers;
+        if data.count_blocks {
+            let blk_att = regs.BLK_ATT.get();
+            let blkcnt = field!(blk_att, BLK_ATT, BLKCNT).saturating_sub(1);
+            regs.BLK_ATT.set(
+                (blk_att & !ral::BLK_ATT::BLKCNT::mask) | (blkcnt << ral::BLK_ATT::BLKCNT::offset),
+            );
+        }
+        match data.blocks.as_mut() {
+            Some(blocks) => {
+                *blocks -= 1;
+                *blocks > 0
+            }
+            None => true,
+        }
+    }
+
+    /// Finish the data phase, issuing any automatic commands.
+    fn complete_transfer(&self, auto_cmd12: bool) {
+        let regs = &self.header.registers;
+        if auto_cmd12 {
+            match self.card.borrow_mut().command(12, 0) {
+                Some(Response::R48(rsp)) => regs.CMD_RSP3.set(rsp),
+                _ => {
+                    regs.AUTOCMD12_ERR_STATUS.set(
+                        regs.AUTOCMD12_ERR_STATUS.get() | ral::AUTOCMD12_ERR_STATUS::AC12TOE::mask,
+                    );
+                    self.signal(Status::AC12E);
+                }
+            }
+        }
+        self.signal(Status::TC);
+    }
+}
+
+/// The state of a [`MemoryCard`].
+///
+/// Values match the `CURRENT_STATE` of the card status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CardState {
+    Idle = 0,
+    Ready = 1,
+    Identification = 2,
+    Standby = 3,
+    Transfer = 4,
+    SendingData = 5,
+    ReceivingData = 6,
+}
+
+/// The data phase expected by a [`MemoryCard`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pending {
+    None,
+    Scr,
+    SdStatus,
+    SwitchFunction,
+    /// Read from the block address. `true` if reading multiple blocks.
+    Read(u32, bool),
+    /// Write to the block address. `true` if writing multiple blocks.
+    Write(u32, bool),
+}
+
+/// A virtual SDHC card backed by memory.
+///
+/// The card implements the commands necessary to initialize an SD card
+/// and transfer blocks. It uses block addressing, and its blocks are 512 bytes.
+pub struct MemoryCard {
+    storage: Vec<u8>,
+    state: CardState,
+    app_cmd: bool,
+    rca: u16,
+    bus_width_4: bool,
+    pending: Pending,
+    erase: (u32, u32),
+    /// Blocks remaining in a closed-ended transfer.
+    block_count: Option<u32>,
+}
+
+/// The size of a card block, in bytes.
+const BLOCK_SIZE: usize = 512;
+
+impl MemoryCard {
+    /// The relative card address that the card publishes.
+    pub const RCA: u16 = 0x1234;
+
+    /// The card identification register.
+    pub const CID: [u32; 4] = [0x1200_0001, 0x0012_3456, 0x4d30_1000, 0x0353_494d];
+
+    /// Create a card with `blocks` number of 512 byte blocks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `blocks` is not a non-zero multiple of 1024. The card's CSD
+    /// expresses capacity in units of 512 KiB.
+    pub fn new(blocks: usize) -> Self {
+        assert!(
+            blocks > 0 && blocks % 1024 == 0,
+            "Block count must be a non-zero multiple of 1024"
+        );
+        Self {
+            storage: vec![0; blocks * BLOCK_SIZE],
+            state: CardState::Idle,
+            app_cmd: false,
+            rca: 0,
+            bus_width_4: false,
+            pending: Pending::None,
+            erase: (0, 0),
+            block_count: None,
+        }
+    }
+
+    /// Returns the card's storage.
+    pub fn storage(&self) -> &[u8] {
+        &self.storage
+    }
+
+    /// Returns the card's storage for modification.
+    pub fn storage_mut(&mut self) -> &mut [u8] {
+        &mut self.storage
+    }
+
+    /// Returns `true` if the host selected a 4-bit bus.
+    pub fn is_bus_width_4(&self) -> bool {
+        self.bus_width_4
+    }
+
+    /// Returns the card specific data register, version 2.0.
+    pub fn csd(&self) -> [u32; 4] {
+        let c_size = (self.storage.len() / BLOCK_SIZE / 1024 - 1) as u128;
+        let csd: u128 = 1 << 126 // CSD_STRUCTURE
+            | 0x0E << 112 // TAAC
+            | 0x32 << 96 // TRAN_SPEED
+            | 0x5B5 << 84 // CCC
+            | 9 << 80 // READ_BL_LEN
+            | c_size << 48 // C_SIZE
+            | 1 << 46 // ERASE_BLK_EN
+            | 0x7F << 39 // SECTOR_SIZE
+            | 2 << 26 // R2W_FACTOR
+            | 9 << 22 // WRITE_BL_LEN
+            | 1; // End bit
+        [
+            csd as u32,
+            (csd >> 32) as u32,
+            (csd >> 64) as u32,
+            (csd >> 96) as u32,
+        ]
+    }
+
+    fn status(&self) -> u32 {
+        (self.state as u32) << 9 | 1 << 8 | (self.app_cmd as u32) << 5
+    }
+
+    fn r1(&self) -> Option<Response> {
+        Some(Response::R48(self.status()))
+    }
+
+    fn blocks(&self) -> u32 {
+        (self.storage.len() / BLOCK_SIZE) as u32
+    }
+
+    fn block(&mut self, address: u32) -> Result<&mut [u8], DataError> {
+        if address >= self.blocks() {
+            return Err(DataError::Timeout);
+        }
+        let start = address as usize * BLOCK_SIZE;
+        Ok(&mut self.storage[start..start + BLOCK_SIZE])
+    }
+
+    /// Count a block in a closed-ended transfer. Returns `true` if
+    /// that was the last block.
+    fn count_block(&mut self) -> bool {
+        match self.block_count.as_mut() {
+            Some(count) => {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    self.block_count = None;
+                    return true;
+                }
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn app_command(&mut self, index: u8, argument: u32) -> Option<Response> {
+        match index {
+            // SET_BUS_WIDTH
+            6 => {
+                self.bus_width_4 = argument & 0b11 == 0b10;
+                self.r1()
+            }
+            // SD_STATUS
+            13 => {
+                self.pending = Pending::SdStatus;
+                self.r1()
+            }
+            // SD_SEND_OP_COND
+            41 => {
+                if argument & 0x00FF_FF00 != 0 {
+                    self.state = CardState::Ready;
+                }
+                // Powered up, SDHC, 2.7 - 3.6V.
+                Some(Response::R48(1 << 31 | (argument & 1 << 30) | 0x00FF_8000))
+            }
+            // SEND_SCR
+            51 => {
+                self.pending = Pending::Scr;
+                self.r1()
+            }
+            _ => self.command(index, argument),
+        }
+    }
+}
+
+impl Card for MemoryCard {
+    fn command(&mut self, index: u8, argument: u32) -> Option<Response> {
+        if core::mem::take(&mut self.app_cmd) && index != 55 {
+            return self.app_command(index, argument);
+        }
+
+        let addressed = argument >> 16 == self.rca as u32;
+        match index {
+            // GO_IDLE_STATE
+            0 => {
+                self.state = CardState::Idle;
+                self.rca = 0;
+                self.bus_width_4 = false;
+                self.pending = Pending::None;
+                self.block_count = None;
+                None
+            }
+            // ALL_SEND_CID
+            2 if self.state == CardState::Ready => {
+                self.state = CardState::Identification;
+                Some(Response::R136(Self::CID))
+            }
+            // SEND_RELATIVE_ADDR
+            3 => {
+                self.rca = Self::RCA;
+                self.state = CardState::Standby;
+                let status = self.status();
+                let condensed = (status >> 8 & 0b111 << 13) | (status & 0x1FFF);
+                Some(Response::R48((self.rca as u32) << 16 | condensed))
+            }
+            // SWITCH_FUNC
+            6 => {
+                self.pending = Pending::SwitchFunction;
+                self.r1()
+            }
+            // SELECT/DESELECT_CARD
+            7 => {
+                if addressed {
+                    self.state = CardState::Transfer;
+                    self.r1()
+                } else {
+                    self.state = CardState::Standby;
+                    None
+                }
+            }
+            // SEND_IF_COND
+            8 => Some(Response::R48(argument & 0xFFF)),
+            // SEND_CSD
+            9 if addressed => Some(Response::R136(self.csd())),
+            // SEND_CID
+            10 if addressed => Some(Response::R136(Self::CID)),
+            // STOP_TRANSMISSION
+            12 => {
+                self.pending = Pending::None;
+                self.state = CardState::Transfer;
+                self.r1()
+            }
+            // SEND_STATUS
+            13 if addressed => self.r1(),
+            // SET_BLOCKLEN
+            16 => self.r1(),
+            // SEND_TUNING_BLOCK, for SD and eMMC.
+            19 | 21 => self.r1(),
+            // SET_BLOCK_COUNT
+            23 => {
+                self.block_count = Some(argument & 0xFFFF);
+                self.r1()
+            }
+            // READ_SINGLE_BLOCK, READ_MULTIPLE_BLOCK
+            17 | 18 => {
+                self.pending = Pending::Read(argument, index == 18);
+                let response = self.r1();
+                self.state = CardState::SendingData;
+                response
+            }
+            // WRITE_BLOCK, WRITE_MULTIPLE_BLOCK
+            24 | 25 => {
+                self.pending = Pending::Write(argument, index == 25);
+                let response = self.r1();
+                self.state = CardState::ReceivingData;
+                response
+            }
+            // ERASE_WR_BLK_START, ERASE_WR_BLK_END
+            32 => {
+                self.erase.0 = argument;
+                self.r1()
+            }
+            33 => {
+                self.erase.1 = argument;
+                self.r1()
+            }
+            // ERASE
+            38 => {
+                let (start, end) = self.erase;
+                for address in start..=end.min(self.blocks().saturating_sub(1)) {
+                    if let Ok(block) = self.block(address) {
+                        block.fill(0);
+                    }
+                }
+                self.r1()
+            }
+            // APP_CMD
+            55 => {
+                self.app_cmd = true;
+                self.r1()
+            }
+            _ => None,
+        }
+    }
+
+    fn read_block(&mut self, block: &mut [u8]) -> Result<(), DataError> {
+        match self.pending {
+            Pending::Scr => {
+                // SD 3.0, SDHC, 1 and 4 bit buses, CMD23 support.
+                let scr = [0x02, 0x35, 0x80, 0x02, 0, 0, 0, 0];
+                let len = block.len().min(scr.len());
+                block[..len].copy_from_slice(&scr[..len]);
+                self.pending = Pending::None;
+            }
+            Pending::SdStatus => {
+                block.fill(0);
+                block[0] = if self.bus_width_4 { 0b10 << 6 } else { 0 };
+                self.pending = Pending::None;
+            }
+            Pending::SwitchFunction => {
+                block.fill(0);
+                // Maximum current, then support for default and high speed.
+                if block.len() >= 14 {
+                    block[0..2].copy_from_slice(&100u16.to_be_bytes());
+                    block[12..14].copy_from_slice(&0x8003u16.to_be_bytes());
+                }
+                self.pending = Pending::None;
+            }
+            Pending::Read(address, multiple) => {
+                let len = block.len().min(BLOCK_SIZE);
+                block[..len].copy_from_slice(&self.block(address)?[..len]);
+                self.pending = Pending::Read(address + 1, multiple);
+                if multiple && !self.count_block() {
+                    return Ok(());
+                }
+                self.pending = Pending::None;
+            }
+            Pending::None | Pending::Write(..) => return Err(DataError::Timeout),
+        }
+        self.state = CardState::Transfer;
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: &[u8]) -> Result<(), DataError> {
+        let Pending::Write(address, multiple) = self.pending else {
+            return Err(DataError::Timeout);
+        };
+        let len = block.len().min(BLOCK_SIZE);
+        self.block(address)?[..len].copy_from_slice(&block[..len]);
+        if multiple && !self.count_block() {
+            self.pending = Pending::Write(address + 1, multiple);
+        } else {
+            self.pending = Pending::None;
+            self.state = CardState::Transfer;
+        }
+        Ok(())
+    }
+}
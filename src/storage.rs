@@ -0,0 +1,272 @@
+//! Block storage on an SD card.
+
+use core::cell::RefCell;
+
+use sdio_host::{
+    common_cmd::{
+        read_multiple_blocks, read_single_block, write_multiple_blocks, write_single_block,
+    },
+    sd::{CSD, SD},
+    BlockingSdioTransport, TransportData,
+};
+
+use crate::{TransportError, Usdhc};
+
+/// The size of a block, in bytes.
+const BLOCK_SIZE: usize = 512;
+
+/// The most blocks in one multi-block transfer.
+///
+/// This is the limit of the peripheral's block counter.
+const MAX_BLOCKS: usize = u16::MAX as usize;
+
+/// Block storage on an initialized SD card.
+///
+/// This reads and writes 512 byte blocks. It uses a multi-block transfer for
+/// each contiguous range of blocks, and single block transfers for a single
+/// block. The capacity comes from the card's CSD.
+///
+/// Initialize the card, select it, and transition it to the transfer state
+/// before creating the storage. A [`BlockingSdioHost`](crate::BlockingSdioHost)
+/// can do this for you; then, take the uSDHC driver from the host's
+/// `transport`, and create the storage from the driver and the host's CSD.
+///
+/// Storage methods return [`TransportError::NotSupported`] if an access
+/// extends beyond the card's capacity.
+///
+/// # Features
+///
+/// Enable the `"embedded-sdmmc"` feature to use the storage as an
+/// `embedded_sdmmc::BlockDevice`. Enable the `"embedded-storage"` feature
+/// to use the storage as `embedded_storage::Storage`. Those traits accept
+/// any byte offset; the storage reads, and rewrites, the blocks around an
+/// unaligned access.
+pub struct BlockStorage {
+    usdhc: RefCell<Usdhc>,
+    card: Card,
+    /// Holds a partial block for an unaligned access.
+    #[cfg(feature = "embedded-storage")]
+    scratch: [u8; BLOCK_SIZE],
+}
+
+/// How to address the card's blocks.
+#[derive(Clone, Copy)]
+struct Card {
+    block_count: u32,
+    /// Standard capacity cards use byte addresses. All other cards use
+    /// block addresses.
+    byte_addressing: bool,
+}
+
+impl Card {
+    fn address(self, block: u32) -> u32 {
+        if self.byte_addressing {
+            block * BLOCK_SIZE as u32
+        } else {
+            block
+        }
+    }
+
+    /// Make sure that the buffer covers whole blocks that are on the card.
+    fn check(self, block: u32, length: usize) -> Result<(), TransportError> {
+        let blocks = (length / BLOCK_SIZE) as u64;
+        if length % BLOCK_SIZE != 0 || block as u64 + blocks > self.block_count as u64 {
+            return Err(TransportError::NotSupported);
+        }
+        Ok(())
+    }
+
+    fn read(self, usdhc: &mut Usdhc, block: u32, buffer: &mut [u8]) -> Result<(), TransportError> {
+        self.check(block, buffer.len())?;
+        for (index, chunk) in buffer.chunks_mut(MAX_BLOCKS * BLOCK_SIZE).enumerate() {
+            let address = self.address(block + (index * MAX_BLOCKS) as u32);
+            let command = if chunk.len() == BLOCK_SIZE {
+                read_single_block(address)
+            } else {
+                read_multiple_blocks(address)
+            };
+            usdhc.transfer(&command, &mut [0; 4], TransportData::Read { buffer: chunk })?;
+        }
+        Ok(())
+    }
+
+    fn write(self, usdhc: &mut Usdhc, block: u32, buffer: &[u8]) -> Result<(), TransportError> {
+        self.check(block, buffer.len())?;
+        for (index, chunk) in buffer.chunks(MAX_BLOCKS * BLOCK_SIZE).enumerate() {
+            let address = self.address(block + (index * MAX_BLOCKS) as u32);
+            let command = if chunk.len() == BLOCK_SIZE {
+                write_single_block(address)
+            } else {
+                write_multiple_blocks(address)
+            };
+            usdhc.transfer(
+                &command,
+                &mut [0; 4],
+                TransportData::Write { buffer: chunk },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockStorage {
+    /// Create block storage for the card described by `csd`.
+    pub fn new(usdhc: Usdhc, csd: CSD<SD>) -> Self {
+        let block_count = (csd.card_size() / BLOCK_SIZE as u64).min(u32::MAX as u64) as u32;
+        Self {
+            usdhc: RefCell::new(usdhc),
+            card: Card {
+                block_count,
+                byte_addressing: csd.version() == 0,
+            },
+            #[cfg(feature = "embedded-storage")]
+            scratch: [0; BLOCK_SIZE],
+        }
+    }
+
+    /// Returns the number of 512 byte blocks on the card.
+    #[inline]
+    pub fn block_count(&self) -> u32 {
+        self.card.block_count
+    }
+
+    /// Read blocks into the buffer, starting at `block`.
+    ///
+    /// The buffer length must be a multiple of 512 bytes.
+    pub fn read_blocks(&mut self, block: u32, buffer: &mut [u8]) -> Result<(), TransportError> {
+        self.card.read(self.usdhc.get_mut(), block, buffer)
+    }
+
+    /// Write blocks from the buffer, starting at `block`.
+    ///
+    /// The buffer length must be a multiple of 512 bytes.
+    pub fn write_blocks(&mut self, block: u32, buffer: &[u8]) -> Result<(), TransportError> {
+        self.card.write(self.usdhc.get_mut(), block, buffer)
+    }
+
+    /// Access the uSDHC driver.
+    pub fn usdhc(&mut self) -> &mut Usdhc {
+        self.usdhc.get_mut()
+    }
+
+    /// Release the uSDHC driver.
+    pub fn release(self) -> Usdhc {
+        self.usdhc.into_inner()
+    }
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+mod sdmmc {
+    use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+
+    use super::BlockStorage;
+    use crate::TransportError;
+
+    // A block is exactly its contents, so a slice of blocks is a slice of bytes.
+    const _: () = assert!(core::mem::size_of::<Block>() == Block::LEN);
+
+    impl BlockDevice for BlockStorage {
+        type Error = TransportError;
+
+        fn read(
+            &self,
+            blocks: &mut [Block],
+            start_block_idx: BlockIdx,
+            _reason: &str,
+        ) -> Result<(), Self::Error> {
+            // Safety: see the size assertion. The bytes are valid for any value.
+            let buffer = unsafe {
+                core::slice::from_raw_parts_mut(
+                    blocks.as_mut_ptr().cast::<u8>(),
+                    blocks.len() * Block::LEN,
+                )
+            };
+            self.card
+                .read(&mut self.usdhc.borrow_mut(), start_block_idx.0, buffer)
+        }
+
+        fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+            // Safety: see the size assertion.
+            let buffer = unsafe {
+                core::slice::from_raw_parts(blocks.as_ptr().cast::<u8>(), blocks.len() * Block::LEN)
+            };
+            self.card
+                .write(&mut self.usdhc.borrow_mut(), start_block_idx.0, buffer)
+        }
+
+        fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+            Ok(BlockCount(self.card.block_count))
+        }
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+mod bytes {
+    use embedded_storage::{ReadStorage, Storage};
+
+    use super::{BlockStorage, BLOCK_SIZE};
+    use crate::TransportError;
+
+    /// Returns the block that holds the byte, and the byte's position in
+    /// the block.
+    fn locate(offset: u64) -> (u32, usize) {
+        (
+            (offset / BLOCK_SIZE as u64) as u32,
+            (offset % BLOCK_SIZE as u64) as usize,
+        )
+    }
+
+    impl ReadStorage for BlockStorage {
+        type Error = TransportError;
+
+        fn read(&mut self, offset: u32, mut bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let usdhc = self.usdhc.get_mut();
+            let mut offset = offset as u64;
+            while !bytes.is_empty() {
+                let (block, skip) = locate(offset);
+                let length = if skip == 0 && bytes.len() >= BLOCK_SIZE {
+                    let length = bytes.len() - bytes.len() % BLOCK_SIZE;
+                    self.card.read(usdhc, block, &mut bytes[..length])?;
+                    length
+                } else {
+                    let length = (BLOCK_SIZE - skip).min(bytes.len());
+                    self.card.read(usdhc, block, &mut self.scratch)?;
+                    bytes[..length].copy_from_slice(&self.scratch[skip..skip + length]);
+                    length
+                };
+                bytes = &mut bytes[length..];
+                offset += length as u64;
+            }
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            (self.card.block_count as u64 * BLOCK_SIZE as u64).min(usize::MAX as u64) as usize
+        }
+    }
+
+    impl Storage for BlockStorage {
+        fn write(&mut self, offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
+            let usdhc = self.usdhc.get_mut();
+            let mut offset = offset as u64;
+            while !bytes.is_empty() {
+                let (block, skip) = locate(offset);
+                let length = if skip == 0 && bytes.len() >= BLOCK_SIZE {
+                    let length = bytes.len() - bytes.len() % BLOCK_SIZE;
+                    self.card.write(usdhc, block, &bytes[..length])?;
+                    length
+                } else {
+                    // Keep the rest of the block.
+                    let length = (BLOCK_SIZE - skip).min(bytes.len());
+                    self.card.read(usdhc, block, &mut self.scratch)?;
+                    self.scratch[skip..skip + length].copy_from_slice(&bytes[..length]);
+                    self.card.write(usdhc, block, &self.scratch)?;
+                    length
+                };
+                bytes = &bytes[length..];
+                offset += length as u64;
+            }
+            Ok(())
+        }
+    }
+}
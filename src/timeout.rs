@@ -0,0 +1,110 @@
+//! Timeouts for operations that wait on the peripheral.
+
+use crate::{PresentState, Status, Usdhc};
+
+/// Bounds the time that the driver waits on the peripheral.
+///
+/// By default, the driver waits forever. Use
+/// [`set_timeout`](crate::Usdhc::set_timeout) to bound every wait. The
+/// timeout applies to each wait, not to an entire operation.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Timeout {
+    /// Never time out.
+    #[default]
+    Never,
+    /// Time out after polling the peripheral this many times.
+    ///
+    /// Use this if you don't have a clock. The wall-clock time of each
+    /// poll depends on your system.
+    Polls(u32),
+    /// Time out once `ticks` elapse on the clock.
+    ///
+    /// `now` returns the current tick count. The count may wrap around.
+    Ticks {
+        /// Returns the current tick count.
+        now: fn() -> u32,
+        /// The number of ticks before timing out.
+        ticks: u32,
+    },
+}
+
+/// The driver timed out while waiting on the peripheral.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimeoutError {
+    /// The software reset never completed.
+    SoftwareReset,
+    /// The command reset never completed.
+    CommandReset,
+    /// The data reset never completed.
+    DataReset,
+    /// The SD clock never stabilized.
+    ClockStable,
+    /// The SD clock never gated off.
+    ClockGate,
+    /// The delay line never locked.
+    DllLock,
+    /// The strobe delay line never locked.
+    StrobeDllLock,
+    /// The command or data lines never became available for the next command.
+    Inhibit(PresentState),
+    /// The status flags never set.
+    Status(Status),
+}
+
+/// Counts down to a timeout.
+struct Countdown {
+    timeout: Timeout,
+    polls: u32,
+    start: u32,
+}
+
+impl Countdown {
+    fn new(timeout: Timeout) -> Self {
+        let start = match timeout {
+            Timeout::Ticks { now, .. } => now(),
+            _ => 0,
+        };
+        Self {
+            timeout,
+            polls: 0,
+            start,
+        }
+    }
+
+    fn expired(&mut self) -> bool {
+        match self.timeout {
+            Timeout::Never => false,
+            Timeout::Polls(polls) => {
+                self.polls = self.polls.saturating_add(1);
+                self.polls > polls
+            }
+            Timeout::Ticks { now, ticks } => now().wrapping_sub(self.start) > ticks,
+        }
+    }
+}
+
+impl Usdhc {
+    /// Set the timeout for all waits.
+    ///
+    /// See [`Timeout`] for more information.
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Timeout) {
+        self.timeout = timeout;
+    }
+
+    /// Poll until `ready` returns something, or until the timeout expires.
+    ///
+    /// Returns `None` on timeout.
+    pub(crate) fn poll<T>(&self, mut ready: impl FnMut(&Self) -> Option<T>) -> Option<T> {
+        let mut countdown = Countdown::new(self.timeout);
+        loop {
+            if let Some(result) = ready(self) {
+                return Some(result);
+            }
+            if countdown.expired() {
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! Sampling clock tuning for SDR104 and HS200.
+
+use core::ops::Range;
+
+use sdio_host::common_cmd::{cmd, R1};
+
+use crate::{ral, DataTransferWidth, PresentState, Status, TimeoutError, TransportError, Usdhc};
+
+/// The command that the card answers with a tuning block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TuningCommand {
+    /// CMD19, SEND_TUNING_BLOCK, for SD cards.
+    SdCmd19,
+    /// CMD21, SEND_TUNING_BLOCK, for eMMC devices.
+    MmcCmd21,
+}
+
+impl TuningCommand {
+    const fn index(self) -> u8 {
+        match self {
+            Self::SdCmd19 => 19,
+            Self::MmcCmd21 => 21,
+        }
+    }
+
+    /// Returns the size of the tuning block, in bytes.
+    ///
+    /// The eMMC tuning block doubles on an 8-bit bus.
+    const fn block_size(self, width: DataTransferWidth) -> u32 {
+        match (self, width) {
+            (Self::MmcCmd21, DataTransferWidth::Bit8) => 128,
+            _ => 64,
+        }
+    }
+}
+
+/// Standard tuning parameters.
+///
+/// The defaults are the peripheral's reset values. The implementation
+/// truncates each field to the width of its register field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StandardTuning {
+    /// The first delay cell tap to test.
+    pub start_tap: u8,
+    /// How many taps to advance after each tuning command.
+    pub step: u8,
+    /// The most tuning commands to send before giving up.
+    pub counter: u8,
+    /// How many consecutive taps need to pass.
+    pub window: u8,
+    /// Let the peripheral adjust the tap during data transfers.
+    ///
+    /// This may delay the need to re-tune.
+    pub auto_tune: bool,
+}
+
+impl Default for StandardTuning {
+    fn default() -> Self {
+        Self {
+            start_tap: 0,
+            step: 1,
+            counter: 40,
+            window: 2,
+            auto_tune: false,
+        }
+    }
+}
+
+/// The results of a manual tuning sweep.
+///
+/// Bit N is set if tuning passed with delay cell tap N.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TapMap(u128);
+
+impl TapMap {
+    /// The number of delay cell taps.
+    pub const TAPS: u8 = 128;
+
+    /// Returns `true` if tuning passed at this tap.
+    pub const fn passed(&self, tap: u8) -> bool {
+        tap < Self::TAPS && self.0 & 1 << tap != 0
+    }
+
+    /// Returns the pass / fail bits for all taps.
+    pub const fn bits(&self) -> u128 {
+        self.0
+    }
+
+    /// Returns the widest range of consecutive passing taps.
+    ///
+    /// If there are multiple widest ranges, this returns the first. Returns
+    /// `None` if no tap passed.
+    pub fn widest_window(&self) -> Option<Range<u8>> {
+        let mut widest: Option<Range<u8>> = None;
+        let mut tap = 0;
+        while tap < Self::TAPS {
+            if !self.passed(tap) {
+                tap += 1;
+                continue;
+            }
+            let start = tap;
+            while tap < Self::TAPS && self.passed(tap) {
+                tap += 1;
+            }
+            if widest
+                .as_ref()
+                .map_or(true, |w| w.len() < (tap - start) as usize)
+            {
+                widest = Some(start..tap);
+            }
+        }
+        widest
+    }
+
+    fn set(&mut self, tap: u8) {
+        self.0 |= 1 << tap;
+    }
+}
+
+/// The tuning that the driver repeats when the peripheral requests re-tuning.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Tuned {
+    Standard(TuningCommand, StandardTuning),
+    Manual(TuningCommand),
+}
+
+/// An error when tuning the sampling clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TuningError {
+    /// A tuning command failed.
+    Transport(TransportError),
+    /// The peripheral didn't find a tap where sampling passes.
+    ///
+    /// The driver restores the fixed sampling clock.
+    Failed,
+}
+
+impl From<TimeoutError> for TuningError {
+    fn from(timeout: TimeoutError) -> Self {
+        Self::Transport(timeout.into())
+    }
+}
+
+impl From<TransportError> for TuningError {
+    fn from(error: TransportError) -> Self {
+        Self::Transport(error)
+    }
+}
+
+impl Usdhc {
+    /// Tune the sampling clock using standard tuning.
+    ///
+    /// The peripheral sweeps the delay cell while the driver sends tuning
+    /// commands. Once the peripheral finds a passing window, it selects the
+    /// tuned sampling clock. Returns the selected tap.
+    ///
+    /// Use this after selecting a [`BusMode`](crate::BusMode) that
+    /// [requires tuning](crate::BusMode::requires_tuning), and after
+    /// setting the bus width.
+    pub fn tune(
+        &mut self,
+        command: TuningCommand,
+        tuning: StandardTuning,
+    ) -> Result<u8, TuningError> {
+        let block_size = command.block_size(self.data_transfer_width());
+
+        self.reset_tuning();
+        ral::modify_reg!(ral, self.inst, TUNING_CTRL,
+            STD_TUNING_EN: 1,
+            TUNING_START_TAP: tuning.start_tap as u32,
+            TUNING_STEP: tuning.step as u32,
+            TUNING_COUNTER: tuning.counter as u32,
+            TUNING_WINDOW: tuning.window as u32
+        );
+        ral::modify_reg!(ral, self.inst, MIX_CTRL, EXE_TUNE: 1, SMP_CLK_SEL: 1);
+
+        // The peripheral clears EXE_TUNE once it's done. If it never finds
+        // a passing window, the counter expires.
+        for _ in 0..=tuning.counter {
+            if let Err(err) = self.send_tuning_command(command, block_size) {
+                self.reset_tuning();
+                return Err(err.into());
+            }
+            if ral::read_reg!(ral, self.inst, MIX_CTRL, EXE_TUNE == 0) {
+                break;
+            }
+        }
+
+        let (exe_tune, smp_clk_sel) =
+            ral::read_reg!(ral, self.inst, MIX_CTRL, EXE_TUNE, SMP_CLK_SEL);
+        if exe_tune == 1 || smp_clk_sel == 0 {
+            self.reset_tuning();
+            return Err(TuningError::Failed);
+        }
+
+        ral::modify_reg!(ral, self.inst, MIX_CTRL, AUTO_TUNE_EN: tuning.auto_tune as u32);
+        self.tuned = Some(Tuned::Standard(command, tuning));
+        Ok(ral::read_reg!(ral, self.inst, CLK_TUNE_CTRL_STATUS, TAP_SEL_PRE) as u8)
+    }
+
+    /// Tune the sampling clock by sweeping every delay cell tap.
+    ///
+    /// Use this if [standard tuning](Self::tune) doesn't converge on your
+    /// board. The driver sends one tuning command for each tap, and records
+    /// if it passed. It then selects the tuned sampling clock at the center of
+    /// the widest passing window. Returns the selected tap, and the pass / fail
+    /// map for all taps.
+    ///
+    /// Returns [`TuningError::Failed`] if no tap passed. In that case, the map
+    /// is empty. The same requirements for standard tuning apply here.
+    pub fn tune_manually(&mut self, command: TuningCommand) -> Result<(u8, TapMap), TuningError> {
+        let block_size = command.block_size(self.data_transfer_width());
+
+        self.reset_tuning();
+
+        let mut map = TapMap::default();
+        for tap in 0..TapMap::TAPS {
+            if !self.select_tap(tap) {
+                continue;
+            }
+            ral::modify_reg!(ral, self.inst, MIX_CTRL, EXE_TUNE: 1, SMP_CLK_SEL: 1);
+            let result = self.send_tuning_command(command, block_size);
+            ral::modify_reg!(ral, self.inst, MIX_CTRL, EXE_TUNE: 0);
+            match result {
+                Ok(()) => map.set(tap),
+                // Get ready for the next tap.
+                Err(_) => {
+                    if let Err(err) = self.command_reset().and_then(|()| self.data_reset()) {
+                        self.reset_tuning();
+                        return Err(err.into());
+                    }
+                }
+            }
+        }
+
+        let Some(window) = map.widest_window() else {
+            self.reset_tuning();
+            return Err(TuningError::Failed);
+        };
+        let tap = window.start + (window.len() as u8 - 1) / 2;
+        self.select_tap(tap);
+        ral::modify_reg!(ral, self.inst, MIX_CTRL, EXE_TUNE: 0, SMP_CLK_SEL: 1);
+        self.tuned = Some(Tuned::Manual(command));
+        Ok((tap, map))
+    }
+
+    /// Returns `true` if the peripheral requests re-tuning.
+    ///
+    /// This only applies after tuning. The peripheral requests re-tuning
+    /// when its re-tuning timer expires, or when it detects sampling errors.
+    pub fn needs_retuning(&self) -> bool {
+        self.tuned.is_some()
+            && (self.present_state().intersects(PresentState::RTR)
+                || self.status().intersects(Status::RTE))
+    }
+
+    /// Repeat the last tuning if the peripheral requests re-tuning.
+    ///
+    /// Returns the newly selected tap, or `None` if there was no need to
+    /// re-tune. The blocking transport calls this before every command, so
+    /// you only need this if you're sending commands yourself.
+    pub fn retune_if_needed(&mut self) -> Result<Option<u8>, TuningError> {
+        if !self.needs_retuning() {
+            return Ok(None);
+        }
+        self.clear_status(Status::RTE);
+        match self.tuned {
+            Some(Tuned::Standard(command, tuning)) => self.tune(command, tuning).map(Some),
+            Some(Tuned::Manual(command)) => self.tune_manually(command).map(|(tap, _)| Some(tap)),
+            None => Ok(None),
+        }
+    }
+
+    /// Load the delay cell tap. Returns `false` if the peripheral rejected the tap.
+    fn select_tap(&mut self, tap: u8) -> bool {
+        ral::modify_reg!(ral, self.inst, CLK_TUNE_CTRL_STATUS, DLY_CELL_SET_PRE: tap as u32);
+        let (pre_err, nxt_err) =
+            ral::read_reg!(ral, self.inst, CLK_TUNE_CTRL_STATUS, PRE_ERR, NXT_ERR);
+        pre_err == 0 && nxt_err == 0
+    }
+
+    /// Stop any tuning, and return to the fixed sampling clock.
+    pub(crate) fn reset_tuning(&mut self) {
+        ral::modify_reg!(ral, self.inst, MIX_CTRL, EXE_TUNE: 0, SMP_CLK_SEL: 0, AUTO_TUNE_EN: 0);
+        ral::modify_reg!(ral, self.inst, TUNING_CTRL, STD_TUNING_EN: 0);
+        self.tuned = None;
+    }
+
+    /// Send one tuning command, and wait for the tuning block.
+    ///
+    /// The tuning block doesn't land in the data buffer, so there's nothing
+    /// to read.
+    fn send_tuning_command(
+        &mut self,
+        command: TuningCommand,
+        block_size: u32,
+    ) -> Result<(), TransportError> {
+        self.prepare_command()?;
+
+        let command = cmd::<R1>(command.index(), 0);
+        ral::modify_reg!(ral, self.inst, MIX_CTRL,
+            DTDSEL: 1,
+            DMAEN: 0,
+            MSBSEL: 0,
+            BCEN: 0,
+            AC12EN: 0,
+            AC23EN: 0
+        );
+        ral::write_reg!(ral, self.inst, BLK_ATT, BLKSIZE: block_size, BLKCNT: 1);
+        ral::write_reg!(ral, self.inst, CMD_ARG, command.arg);
+        self.suspend_card_detect();
+        ral::write_reg!(ral, self.inst, CMD_XFR_TYP,
+            CMDINX: command.cmd as u32,
+            DPSEL: 1,
+            CICEN: 1,
+            CCCEN: 1,
+            RSPTYP: 2
+        );
+
+        let result = self
+            .wait_for(Status::CC)
+            .and_then(|_| self.wait_for(Status::BRR));
+        self.resume_card_detect();
+        result
+    }
+}
@@ -0,0 +1,103 @@
+//! Signaling voltage switch for UHS-I cards.
+
+use sdio_host::{
+    common_cmd::{cmd, R1},
+    BlockingSdioTransport, TransportData,
+};
+
+use crate::{ral, PresentState, TimeoutError, TransportError, Usdhc};
+
+/// CMD11, VOLTAGE_SWITCH.
+const VOLTAGE_SWITCH: u8 = 11;
+
+/// `DAT[3:0]` signal levels.
+const DAT3_0: u8 = 0b1111;
+
+/// An error when switching to 1.8V signaling.
+///
+/// After any error other than [`Command`](Self::Command), power cycle
+/// the card before trying again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VoltageSwitchError {
+    /// The host doesn't support 1.8V signaling.
+    NotSupported,
+    /// The card didn't accept CMD11.
+    Command(TransportError),
+    /// The card didn't drive CMD and `DAT[3:0]` low after CMD11.
+    ///
+    /// The host is still using 3.3V signaling.
+    NotStarted,
+    /// CMD and `DAT[3:0]` weren't high after the switch.
+    ///
+    /// The driver returns the host to 3.3V signaling.
+    NotCompleted,
+    /// The driver timed out while waiting for the card clock to gate.
+    Timeout(TimeoutError),
+}
+
+impl From<TimeoutError> for VoltageSwitchError {
+    fn from(timeout: TimeoutError) -> Self {
+        Self::Timeout(timeout)
+    }
+}
+
+impl Usdhc {
+    /// Switch the card and host to 1.8V signaling.
+    ///
+    /// This sends CMD11 to the card, then performs the UHS-I voltage switch
+    /// sequence. `delay` blocks for the given number of milliseconds. Use this
+    /// after the card indicates 1.8V support in its ACMD41 response, and before
+    /// selecting a UHS-I [`BusMode`](crate::BusMode).
+    ///
+    /// This only selects the signaling voltage in the peripheral. It's your
+    /// responsibility to configure any external regulator or pad voltage that
+    /// follows the uSDHC VSELECT signal.
+    pub fn switch_to_1v8(&mut self, delay: &mut impl FnMut(u32)) -> Result<(), VoltageSwitchError> {
+        if !self.capabilities().voltage_1v8 {
+            return Err(VoltageSwitchError::NotSupported);
+        }
+
+        let mut response = [0; 4];
+        self.transfer(
+            &cmd::<R1>(VOLTAGE_SWITCH, 0),
+            &mut response,
+            TransportData::None,
+        )
+        .map_err(VoltageSwitchError::Command)?;
+
+        // The card clock gates when idle, unless we're forcing it on.
+        ral::modify_reg!(ral, self.inst, VEND_SPEC, FRC_SDCLK_ON: 0);
+        self.poll(|usdhc| {
+            usdhc
+                .present_state()
+                .intersects(PresentState::SDOFF)
+                .then_some(())
+        })
+        .ok_or(TimeoutError::ClockGate)?;
+
+        // The card acknowledges the switch by driving its lines low.
+        if self.present_state().intersects(PresentState::CLSL)
+            || self.data_line_levels() & DAT3_0 != 0
+        {
+            return Err(VoltageSwitchError::NotStarted);
+        }
+
+        ral::modify_reg!(ral, self.inst, VEND_SPEC, VSELECT: 1);
+        delay(5);
+
+        // Run the clock for at least 1ms, so the card can finish the switch.
+        ral::modify_reg!(ral, self.inst, VEND_SPEC, FRC_SDCLK_ON: 1);
+        delay(1);
+        ral::modify_reg!(ral, self.inst, VEND_SPEC, FRC_SDCLK_ON: 0);
+
+        if !self.present_state().intersects(PresentState::CLSL)
+            || self.data_line_levels() & DAT3_0 != DAT3_0
+        {
+            ral::modify_reg!(ral, self.inst, VEND_SPEC, VSELECT: 0);
+            return Err(VoltageSwitchError::NotCompleted);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//! A waker that an interrupt handler can share with a task.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+};
+
+/// Nobody is registering or waking.
+const WAITING: usize = 0;
+/// A task is registering its waker.
+const REGISTERING: usize = 0b01;
+/// An interrupt is waking the task.
+const WAKING: usize = 0b10;
+
+/// Holds the waker for the task that's awaiting the uSDHC interrupt.
+///
+/// The async driver registers its task's waker, and the uSDHC interrupt
+/// handler wakes that task. Declare one of these as a `static` for each uSDHC
+/// instance. This works with any executor.
+///
+/// This requires atomic compare-and-swap.
+pub struct AtomicWaker {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// Safety: the state serializes access to the waker.
+unsafe impl Send for AtomicWaker {}
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+    /// Create a waker with nothing registered.
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Register the waker that [`wake`](Self::wake) wakes.
+    ///
+    /// This replaces any previously-registered waker. If a wake happens
+    /// while registering, this wakes the new waker.
+    pub fn register(&self, waker: &Waker) {
+        match self
+            .state
+            .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+            .unwrap_or_else(|state| state)
+        {
+            WAITING => {
+                // Safety: the REGISTERING state gives us exclusive access
+                // to the waker.
+                let previous = unsafe { (*self.waker.get()).replace(waker.clone()) };
+                if self
+                    .state
+                    .compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    // Someone tried to wake while we were registering. They
+                    // left the waking to us.
+                    //
+                    // Safety: we still own the waker until we leave the
+                    // REGISTERING state.
+                    let waker = unsafe { (*self.waker.get()).take() };
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+                drop(previous);
+            }
+            // An interrupt is waking the previous waker. Make sure that this
+            // task runs again.
+            WAKING => waker.wake_by_ref(),
+            // Another context is registering. That's a bug in the caller,
+            // but there's nothing to corrupt.
+            _ => {}
+        }
+    }
+
+    /// Wake the registered waker, if any.
+    ///
+    /// Call this from your interrupt handler.
+    pub fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    /// Take the registered waker, if any.
+    fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                // Safety: the WAKING state gives us exclusive access to
+                // the waker.
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            }
+            // The registering context will see WAKING, and it will wake
+            // the task.
+            _ => None,
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for AtomicWaker {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AtomicWaker").finish_non_exhaustive()
+    }
+}
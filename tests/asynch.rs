@@ -0,0 +1,217 @@
+//! Tests for the async transport.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+use common::{init, pattern, setup, RCA};
+use imxrt_usdhc::{
+    AsyncUsdhc, AtomicWaker, CardDetect, CardError, Status, TransferState, TransportError,
+};
+use sdio_host::{
+    common_cmd::{self, cmd, R1},
+    sd::BusWidth,
+    TransportData, TransportMode,
+};
+
+/// A waker that records when it's woken.
+#[derive(Default)]
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A delay that yields to the executor once.
+struct Yield(bool);
+
+impl Future for Yield {
+    type Output = ();
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if core::mem::replace(&mut self.0, true) {
+            return Poll::Ready(());
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Poll the future until it's ready.
+///
+/// The simulator finishes each transfer before the transport waits for an
+/// interrupt, so the future shouldn't stay pending.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Flag::default()));
+    let mut context = Context::from_waker(&waker);
+    for _ in 0..100 {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+    panic!("The future never completed");
+}
+
+#[test]
+fn async_transfers() {
+    static WAKER: AtomicWaker = AtomicWaker::new();
+    let (sim, usdhc) = setup();
+    let mut usdhc = AsyncUsdhc::new(usdhc, &WAKER);
+
+    block_on(usdhc.power_cycle(&mut |_| async {})).unwrap();
+    init(usdhc.usdhc());
+    block_on(usdhc.set_bus_width(BusWidth::Four)).unwrap();
+    block_on(usdhc.set_mode(TransportMode::HighSpeed)).unwrap();
+
+    let data = pattern(1024, 7);
+    let mut response = [0; 4];
+    // Safety: the transfer runs to completion.
+    block_on(unsafe {
+        usdhc.transfer(
+            &common_cmd::write_multiple_blocks(3),
+            &mut response,
+            TransportData::Write { buffer: &data },
+        )
+    })
+    .unwrap();
+    assert_eq!(&sim.card().storage()[3 * 512..5 * 512], &data[..]);
+
+    let mut buffer = [0; 1024];
+    // Safety: the transfer runs to completion.
+    block_on(unsafe {
+        usdhc.transfer(
+            &common_cmd::read_multiple_blocks(3),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        )
+    })
+    .unwrap();
+    assert_eq!(&buffer[..], &data[..]);
+    assert_eq!(usdhc.usdhc().transfer_state(), TransferState::Idle);
+
+    sim.set_card_inserted(false);
+    // Safety: the transfer never starts.
+    assert_eq!(
+        block_on(unsafe {
+            usdhc.transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        }),
+        Err(CardError::Transport(TransportError::CommandTimeout))
+    );
+
+    // With card detection, the transport reports the missing card.
+    usdhc.usdhc().set_card_detect(Some(CardDetect::default()));
+    // Safety: the transfer never starts.
+    assert_eq!(
+        block_on(unsafe {
+            usdhc.transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        }),
+        Err(CardError::NoCard)
+    );
+    assert_eq!(
+        block_on(usdhc.power_cycle(&mut |_| async {})),
+        Err(CardError::NoCard)
+    );
+}
+
+#[test]
+fn interrupt_masks_and_wakes() {
+    static WAKER: AtomicWaker = AtomicWaker::new();
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    let ptr = sim.as_ptr();
+    let mut usdhc = AsyncUsdhc::new(usdhc, &WAKER);
+
+    let flag = Arc::new(Flag::default());
+    WAKER.register(&Waker::from(flag.clone()));
+
+    let inner = usdhc.usdhc();
+    inner.set_status_interrupt(Status::CINS);
+    // Safety: there's no data phase.
+    unsafe {
+        inner
+            .start_transfer(&cmd::<R1>(13, RCA), TransportData::None)
+            .unwrap()
+    };
+    assert!(sim.interrupt_pending());
+
+    // Safety: the pointer is for this transport's peripheral.
+    unsafe { AsyncUsdhc::on_interrupt(ptr, &WAKER) };
+    assert!(!sim.interrupt_pending());
+    assert!(flag.0.load(Ordering::SeqCst));
+    // The handler only masks the pending signals.
+    assert!(!inner.status_interrupt().contains(Status::CC));
+    assert!(inner.status_interrupt().contains(Status::CINS));
+
+    assert_eq!(inner.on_interrupt(), TransferState::Complete);
+    assert!(inner.transfer_result().unwrap().is_ok());
+}
+
+#[test]
+fn driver_access_aborts_the_transfer() {
+    static WAKER: AtomicWaker = AtomicWaker::new();
+    let (_sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    let mut usdhc = AsyncUsdhc::new(usdhc, &WAKER);
+
+    // Safety: there's no data phase.
+    unsafe {
+        usdhc
+            .usdhc()
+            .start_transfer(&cmd::<R1>(13, RCA), TransportData::None)
+            .unwrap()
+    };
+    assert_eq!(usdhc.usdhc().transfer_state(), TransferState::Idle);
+    assert!(usdhc.usdhc().transfer_result().is_none());
+
+    // Safety: there's no data phase.
+    unsafe {
+        usdhc
+            .usdhc()
+            .start_transfer(&cmd::<R1>(13, RCA), TransportData::None)
+            .unwrap()
+    };
+    let usdhc = usdhc.release();
+    assert_eq!(usdhc.transfer_state(), TransferState::Idle);
+    assert_eq!(usdhc.status_interrupt(), Status::empty());
+}
+
+#[test]
+fn power_cycle_awaits_the_delay() {
+    static WAKER: AtomicWaker = AtomicWaker::new();
+    let (_sim, usdhc) = setup();
+    let mut usdhc = AsyncUsdhc::new(usdhc, &WAKER);
+
+    let mut delays = Vec::new();
+    let mut delay = |ms| {
+        delays.push(ms);
+        Yield(false)
+    };
+    let waker = Waker::from(Arc::new(Flag::default()));
+    let mut context = Context::from_waker(&waker);
+    let mut polls = 1;
+    {
+        let mut future = pin!(usdhc.power_cycle(&mut delay));
+        while future.as_mut().poll(&mut context).is_pending() {
+            polls += 1;
+        }
+    }
+    // The power cycle yields during every delay.
+    assert_eq!(polls, 4);
+    assert_eq!(delays, [100, 5, 5]);
+
+    init(usdhc.usdhc());
+}
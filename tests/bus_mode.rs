@@ -0,0 +1,114 @@
+//! Tests for bus modes.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, offset, register, setup};
+use imxrt_usdhc::{BusMode, DataTransferWidth, DllConfig, ModeError, Usdhc};
+use sdio_host::{
+    common_cmd, sd::BusWidth, BlockingSdioTransport, TransportData, TransportError, TransportMode,
+};
+
+// MIX_CTRL fields.
+const DDR_EN: u32 = 1 << 3;
+
+#[test]
+fn transport_modes() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    // Identification runs at 198 MHz / (256 * 2), about 386 kHz.
+    usdhc.power_cycle(&mut |_| {}).unwrap();
+    assert_eq!(usdhc.bus_mode(), BusMode::Identification);
+    assert_eq!(register(&sim, offset::SYS_CTRL) >> 4 & 0xFFF, 0x80 << 4 | 1);
+
+    usdhc.set_mode(TransportMode::DefaultSpeed).unwrap();
+    assert_eq!(usdhc.bus_mode(), BusMode::SdDefaultSpeed);
+    usdhc.set_mode(TransportMode::HighSpeed).unwrap();
+    assert_eq!(usdhc.bus_mode(), BusMode::SdHighSpeed);
+}
+
+#[test]
+fn bus_modes() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    assert_eq!(usdhc.set_bus_mode(BusMode::SdHighSpeed), Ok(49_500_000));
+    assert_eq!(usdhc.set_bus_mode(BusMode::MmcHighSpeed), Ok(49_500_000));
+
+    // DDR modes need a wider bus.
+    assert_eq!(
+        usdhc.set_bus_mode(BusMode::SdDdr50),
+        Err(ModeError::BusWidth)
+    );
+    usdhc.set_data_transfer_width(DataTransferWidth::Bit4);
+    assert_eq!(usdhc.set_bus_mode(BusMode::SdDdr50), Ok(49_500_000));
+    assert_ne!(register(&sim, offset::MIX_CTRL) & DDR_EN, 0);
+    assert_eq!(usdhc.set_mode(TransportMode::DefaultSpeed), Ok(()));
+    assert_eq!(register(&sim, offset::MIX_CTRL) & DDR_EN, 0);
+
+    assert_eq!(usdhc.set_bus_mode(BusMode::SdSdr104), Ok(198_000_000));
+}
+
+#[test]
+fn unreachable_clocks() {
+    let (sim, _usdhc) = setup();
+    // Safety: the simulator outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), 4_000_000_000) };
+    assert_eq!(
+        usdhc.set_bus_mode(BusMode::Identification),
+        Err(ModeError::ClockUnreachable)
+    );
+    // The transport reports that it can't identify cards.
+    assert_eq!(
+        usdhc.power_cycle(&mut |_| {}),
+        Err(TransportError::NotSupported)
+    );
+}
+
+#[test]
+fn ddr() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_bus_width(BusWidth::Four).unwrap();
+    usdhc.set_ddr_dll(Some(DllConfig {
+        delay_target: 0x10,
+        ..Default::default()
+    }));
+
+    // The DDR prescaler halves the root clock.
+    assert_eq!(usdhc.set_bus_mode(BusMode::SdDdr50), Ok(49_500_000));
+    assert_eq!(usdhc.bus_mode(), BusMode::SdDdr50);
+    assert_ne!(register(&sim, offset::MIX_CTRL) & DDR_EN, 0);
+    assert!(usdhc.dll_status().slave_lock);
+    assert_eq!(
+        usdhc.set_bus_width(BusWidth::One),
+        Err(TransportError::NotSupported)
+    );
+
+    // Single block transfers need whole blocks.
+    let mut response = [0; 4];
+    let mut small = [0; 64];
+    assert_eq!(
+        usdhc.transfer(
+            &common_cmd::read_single_block(5),
+            &mut response,
+            TransportData::Read { buffer: &mut small },
+        ),
+        Err(TransportError::NotSupported)
+    );
+    let mut block = [0; 512];
+    usdhc
+        .transfer(
+            &common_cmd::read_single_block(5),
+            &mut response,
+            TransportData::Read { buffer: &mut block },
+        )
+        .unwrap();
+
+    usdhc.set_bus_mode(BusMode::SdHighSpeed).unwrap();
+    assert!(!usdhc.dll_status().slave_lock);
+    usdhc.power_cycle(&mut |_| {}).unwrap();
+    assert_eq!(usdhc.bus_mode(), BusMode::Identification);
+}
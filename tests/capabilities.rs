@@ -0,0 +1,96 @@
+//! Tests for the host capabilities.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, pattern, setup};
+use imxrt_usdhc::{sim, BusMode, DmaSelect, ModeError, VoltageSwitchError};
+use sdio_host::{
+    common_cmd::{self, cmd, R1},
+    sd::BusWidth,
+    BlockingSdioTransport, TransportData, TransportError,
+};
+
+// HOST_CTRL_CAP fields.
+const SDR104_SUPPORT: u32 = 1 << 1;
+const DDR50_SUPPORT: u32 = 1 << 2;
+const MBL: u32 = 0b111 << 16;
+const ADMAS: u32 = 1 << 20;
+const DMAS: u32 = 1 << 22;
+const VS18: u32 = 1 << 26;
+
+#[test]
+fn reset_capabilities() {
+    let (_sim, usdhc) = setup();
+    let capabilities = usdhc.capabilities();
+    assert!(capabilities.sdr104 && capabilities.ddr50);
+    assert!(capabilities.voltage_1v8 && capabilities.adma && capabilities.dma);
+    assert_eq!(capabilities.max_block_length, 4096);
+    assert!(capabilities.supports(BusMode::MmcHs400));
+}
+
+#[test]
+fn transport_respects_capabilities() {
+    let (sim, mut usdhc) = setup();
+    sim.set_capabilities(sim::HOST_CTRL_CAP & !(SDR104_SUPPORT | DDR50_SUPPORT | MBL | VS18));
+    init(&mut usdhc);
+
+    let capabilities = usdhc.capabilities();
+    assert_eq!(capabilities.max_block_length, 512);
+    assert!(!capabilities.supports(BusMode::SdSdr104));
+    assert!(capabilities.supports(BusMode::SdHighSpeed));
+
+    usdhc.set_bus_width(BusWidth::Four).unwrap();
+    assert_eq!(
+        usdhc.set_bus_mode(BusMode::SdDdr50),
+        Err(ModeError::NotSupported)
+    );
+    assert_eq!(
+        usdhc.set_bus_mode(BusMode::SdSdr50),
+        Err(ModeError::NotSupported)
+    );
+    assert_eq!(
+        usdhc.switch_to_1v8(&mut |_| {}),
+        Err(VoltageSwitchError::NotSupported)
+    );
+
+    // Blocks can't exceed the maximum block length.
+    let mut response = [0; 4];
+    let mut large = [0; 1024];
+    assert_eq!(
+        usdhc.transfer(
+            &cmd::<R1>(17, 0),
+            &mut response,
+            TransportData::Read { buffer: &mut large },
+        ),
+        Err(TransportError::NotSupported)
+    );
+}
+
+#[test]
+fn cpu_moves_data_without_dma() {
+    // No DMA memory, so any DMA would fail.
+    let (sim, mut usdhc) = setup();
+    sim.set_capabilities(sim::HOST_CTRL_CAP & !(ADMAS | DMAS));
+    usdhc.set_dma_enable(Some(DmaSelect::Simple));
+    init(&mut usdhc);
+
+    let data = pattern(512, 31);
+    sim.card().storage_mut()[..512].copy_from_slice(&data);
+    // Without the capabilities, the transport would use DMA for this buffer.
+    #[repr(C, align(4))]
+    struct Buffer([u8; 512]);
+    let mut buffer = Buffer([0; 512]);
+    let mut response = [0; 4];
+    usdhc
+        .transfer(
+            &common_cmd::read_single_block(0),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer.0,
+            },
+        )
+        .unwrap();
+    assert_eq!(&buffer.0[..], &data[..]);
+}
@@ -0,0 +1,130 @@
+//! Tests for card detection.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, setup, RCA};
+use imxrt_usdhc::{CardDetect, CardError, CardEvent, NoCard, Status, TransportError};
+use sdio_host::{
+    common_cmd::{cmd, R1},
+    BlockingSdioTransport, TransportData,
+};
+
+#[test]
+fn poll_card_detect() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    // Without detection, nothing reports.
+    assert_eq!(usdhc.poll_card_detect(), None);
+
+    usdhc.set_card_detect(Some(CardDetect::default()));
+    assert!(usdhc.card_present());
+
+    // The removal reports after three samples.
+    sim.set_card_inserted(false);
+    assert!(usdhc.card_present());
+    assert_eq!(usdhc.poll_card_detect(), None);
+    assert_eq!(usdhc.poll_card_detect(), None);
+    assert_eq!(usdhc.poll_card_detect(), Some(CardEvent::Removed));
+    assert_eq!(usdhc.poll_card_detect(), None);
+    assert!(!usdhc.card_present());
+
+    // A bounce shorter than the debounce doesn't report.
+    sim.set_card_inserted(true);
+    assert_eq!(usdhc.poll_card_detect(), None);
+    sim.set_card_inserted(false);
+    assert_eq!(usdhc.poll_card_detect(), None);
+    assert!(!usdhc.card_present());
+
+    // Disabling detection reflects the card directly.
+    usdhc.set_card_detect(None);
+    sim.set_card_inserted(true);
+    assert!(usdhc.card_present());
+    sim.set_card_inserted(false);
+    assert!(!usdhc.card_present());
+}
+
+#[test]
+fn missing_card() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_card_detect(Some(CardDetect::default()));
+    assert_eq!(usdhc.check_card(), Ok(()));
+
+    sim.set_card_inserted(false);
+    assert_eq!(usdhc.check_card(), Err(NoCard));
+    let mut response = [0; 4];
+    assert_eq!(
+        usdhc.transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None),
+        Err(TransportError::CommandTimeout)
+    );
+    assert_eq!(
+        usdhc.power_cycle(&mut |_| {}),
+        Err(TransportError::CommandTimeout)
+    );
+
+    // The driver tells the missing card from a timeout.
+    assert_eq!(usdhc.check_card(), Err(NoCard));
+    assert_eq!(
+        usdhc.card_transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None),
+        Err(CardError::NoCard)
+    );
+    assert_eq!(usdhc.card_power_cycle(&mut |_| {}), Err(CardError::NoCard));
+
+    // Without card detection, it's only a timeout.
+    usdhc.set_card_detect(None);
+    assert_eq!(
+        usdhc.card_transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None),
+        Err(CardError::Transport(TransportError::CommandTimeout))
+    );
+}
+
+#[test]
+fn card_returns() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_card_detect(Some(CardDetect::default()));
+
+    sim.set_card_inserted(false);
+    sim.set_card_inserted(true);
+    assert!(usdhc.status().contains(Status::CRM));
+
+    // The stale removal doesn't fail the transport.
+    assert_eq!(usdhc.check_card(), Ok(()));
+    assert!(!usdhc.status().contains(Status::CRM));
+    usdhc.card_power_cycle(&mut |_| {}).unwrap();
+    init(&mut usdhc);
+    let mut response = [0; 4];
+    usdhc
+        .card_transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        .unwrap();
+}
+
+#[test]
+fn card_detect_interrupt() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_card_detect(Some(CardDetect::default()));
+    usdhc.set_status_interrupt(Status::CINS | Status::CRM);
+
+    sim.set_card_inserted(false);
+    assert!(sim.interrupt_pending());
+    assert_eq!(usdhc.on_card_detect_interrupt(), Some(CardEvent::Removed));
+    assert!(!sim.interrupt_pending());
+    assert!(!usdhc.card_present());
+
+    sim.set_card_inserted(true);
+    assert!(sim.interrupt_pending());
+    assert_eq!(usdhc.on_card_detect_interrupt(), Some(CardEvent::Inserted));
+    assert!(!sim.interrupt_pending());
+    assert!(usdhc.card_present());
+
+    // Without a change, there's no event.
+    assert_eq!(usdhc.on_card_detect_interrupt(), None);
+    init(&mut usdhc);
+    let mut response = [0; 4];
+    usdhc
+        .transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        .unwrap();
+}
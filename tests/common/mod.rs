@@ -0,0 +1,94 @@
+//! Helpers for the simulator tests.
+
+#![allow(dead_code)]
+
+use imxrt_usdhc::{
+    sim::{Card, MemoryCard, Simulator},
+    Usdhc,
+};
+use sdio_host::{
+    common_cmd::{self, cmd, R1, R2, R3},
+    BlockingSdioTransport, TransportData,
+};
+
+/// The root clock frequency for all tests.
+pub const ROOT_CLOCK_HZ: u32 = 198_000_000;
+
+/// The card's relative address, positioned for a command argument.
+pub const RCA: u32 = (MemoryCard::RCA as u32) << 16;
+
+/// Create a simulator with a 512 KiB card, and a driver for that simulator.
+///
+/// The simulator must outlive the driver.
+pub fn setup() -> (Box<Simulator<MemoryCard>>, Usdhc) {
+    let sim = Simulator::new(MemoryCard::new(1024));
+    // Safety: the simulator is boxed, so it doesn't move. Each test keeps
+    // the simulator alive for as long as the driver.
+    let usdhc = unsafe { Usdhc::new(sim.as_ptr(), ROOT_CLOCK_HZ) };
+    (sim, usdhc)
+}
+
+/// Power cycle, identify, and select the card.
+///
+/// The card is in the transfer state once this returns.
+pub fn init(usdhc: &mut Usdhc) {
+    let mut response = [0; 4];
+    usdhc.power_cycle(&mut |_| {}).unwrap();
+    usdhc
+        .transfer(&common_cmd::idle(), &mut response, TransportData::None)
+        .unwrap();
+    usdhc
+        .transfer(&cmd::<R1>(8, 0x1AA), &mut response, TransportData::None)
+        .unwrap();
+    usdhc
+        .transfer(&cmd::<R1>(55, 0), &mut response, TransportData::None)
+        .unwrap();
+    usdhc
+        .transfer(
+            &cmd::<R3>(41, 0x40FF_8000),
+            &mut response,
+            TransportData::None,
+        )
+        .unwrap();
+    usdhc
+        .transfer(&cmd::<R2>(2, 0), &mut response, TransportData::None)
+        .unwrap();
+    usdhc
+        .transfer(&cmd::<R1>(3, 0), &mut response, TransportData::None)
+        .unwrap();
+    usdhc
+        .transfer(&cmd::<R1>(7, RCA), &mut response, TransportData::None)
+        .unwrap();
+}
+
+/// Returns a buffer of `length` bytes with a pattern derived from `seed`.
+pub fn pattern(length: usize, seed: u8) -> Vec<u8> {
+    (0..length)
+        .map(|index| {
+            (index as u8)
+                .wrapping_mul(seed)
+                .wrapping_add(index as u8 >> 3)
+        })
+        .collect()
+}
+
+/// Read a raw register from the simulator.
+pub fn register<C: Card>(sim: &Simulator<C>, offset: usize) -> u32 {
+    // Safety: the offset is within the register block, and it's word aligned.
+    unsafe { sim.as_ptr().cast::<u32>().add(offset / 4).read_volatile() }
+}
+
+/// Offsets of the registers that tests inspect.
+pub mod offset {
+    pub const DS_ADDR: usize = 0x00;
+    pub const BLK_ATT: usize = 0x04;
+    pub const CMD_XFR_TYP: usize = 0x0C;
+    pub const PROT_CTRL: usize = 0x28;
+    pub const SYS_CTRL: usize = 0x2C;
+    pub const INT_SIGNAL_EN: usize = 0x38;
+    pub const MIX_CTRL: usize = 0x48;
+    pub const ADMA_SYS_ADDR: usize = 0x58;
+    pub const VEND_SPEC: usize = 0xC0;
+    pub const MMC_BOOT: usize = 0xC4;
+    pub const VEND_SPEC2: usize = 0xC8;
+}
@@ -0,0 +1,123 @@
+//! Tests for card detection through DAT3, and the test level.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, offset, pattern, register, setup, RCA};
+use imxrt_usdhc::{CardDetect, CardDetectSource, CardEvent, NoCard, TransferState};
+use sdio_host::{
+    common_cmd::{self, cmd, R1},
+    sd::BusWidth,
+    BlockingSdioTransport, TransportData,
+};
+
+// PROT_CTRL fields.
+const D3CD: u32 = 1 << 3;
+const CDSS: u32 = 1 << 7;
+
+const DAT3: CardDetect = CardDetect {
+    source: CardDetectSource::Dat3,
+    debounce: 3,
+};
+
+#[test]
+fn dat3_without_a_switch() {
+    let (sim, mut usdhc) = setup();
+    sim.set_card_detect_switch(false);
+
+    // The pin never detects the card.
+    usdhc.set_card_detect(Some(CardDetect::default()));
+    assert!(!usdhc.card_present());
+    assert_eq!(usdhc.check_card(), Err(NoCard));
+
+    usdhc.set_card_detect(Some(DAT3));
+    assert!(usdhc.card_present());
+    // The selection survives the power cycle.
+    init(&mut usdhc);
+    assert_eq!(register(&sim, offset::PROT_CTRL) & (D3CD | CDSS), D3CD);
+    assert_eq!(usdhc.check_card(), Ok(()));
+
+    sim.set_card_inserted(false);
+    assert_eq!(usdhc.poll_card_detect(), None);
+    assert_eq!(usdhc.poll_card_detect(), None);
+    assert_eq!(usdhc.poll_card_detect(), Some(CardEvent::Removed));
+    assert_eq!(usdhc.check_card(), Err(NoCard));
+}
+
+#[test]
+fn dat3_released_for_wide_transfers() {
+    let (sim, mut usdhc) = setup();
+    sim.set_card_detect_switch(false);
+    usdhc.set_card_detect(Some(DAT3));
+    init(&mut usdhc);
+    usdhc.set_bus_width(BusWidth::Four).unwrap();
+
+    let data = pattern(1024, 5);
+    let mut response = [0; 4];
+    usdhc
+        .transfer(
+            &common_cmd::write_multiple_blocks(2),
+            &mut response,
+            TransportData::Write { buffer: &data },
+        )
+        .unwrap();
+    let mut buffer = [0; 1024];
+    usdhc
+        .transfer(
+            &common_cmd::read_multiple_blocks(2),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        )
+        .unwrap();
+    assert_eq!(&buffer[..], &data[..]);
+    // DAT3 detection is back once the transfer completes.
+    assert_eq!(register(&sim, offset::PROT_CTRL) & (D3CD | CDSS), D3CD);
+
+    // Safety: the buffer outlives the transfer.
+    unsafe {
+        usdhc
+            .start_transfer(
+                &common_cmd::read_multiple_blocks(2),
+                TransportData::Read {
+                    buffer: &mut buffer,
+                },
+            )
+            .unwrap()
+    };
+    // The test level holds the card present during the transfer.
+    assert_eq!(register(&sim, offset::PROT_CTRL) & (D3CD | CDSS), CDSS);
+    while sim.interrupt_pending() {
+        usdhc.on_interrupt();
+    }
+    assert_eq!(usdhc.transfer_state(), TransferState::Complete);
+    assert!(usdhc.transfer_result().unwrap().is_ok());
+    assert_eq!(register(&sim, offset::PROT_CTRL) & (D3CD | CDSS), D3CD);
+
+    // No data on DAT3, so there's no change.
+    usdhc
+        .transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        .unwrap();
+    assert_eq!(register(&sim, offset::PROT_CTRL) & (D3CD | CDSS), D3CD);
+}
+
+#[test]
+fn test_level() {
+    let (sim, mut usdhc) = setup();
+    usdhc.set_card_detect(Some(CardDetect {
+        source: CardDetectSource::TestLevel(false),
+        ..Default::default()
+    }));
+    assert!(!usdhc.card_present());
+    assert_eq!(usdhc.check_card(), Err(NoCard));
+
+    sim.set_card_detect_switch(false);
+    usdhc.set_card_detect(Some(CardDetect {
+        source: CardDetectSource::TestLevel(true),
+        ..Default::default()
+    }));
+    assert!(usdhc.card_present());
+    init(&mut usdhc);
+}
@@ -0,0 +1,42 @@
+//! Tests for the delay lines.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::setup;
+use imxrt_usdhc::{DllConfig, Timeout, TimeoutError};
+
+#[test]
+fn dll_locks() {
+    let (_sim, mut usdhc) = setup();
+    assert!(!usdhc.dll_status().slave_lock);
+    usdhc.set_timeout(Timeout::Polls(5));
+    assert_eq!(usdhc.wait_for_dll_lock(), Err(TimeoutError::DllLock));
+
+    usdhc
+        .enable_dll(DllConfig {
+            delay_target: 0x35,
+            ..Default::default()
+        })
+        .unwrap();
+    let status = usdhc.dll_status();
+    assert!(status.slave_lock && status.reference_lock);
+    assert_eq!(status.slave_select, 0x35);
+    usdhc.wait_for_dll_lock().unwrap();
+
+    usdhc.disable_dll();
+    assert!(!usdhc.dll_status().slave_lock);
+}
+
+#[test]
+fn dll_override() {
+    let (_sim, mut usdhc) = setup();
+    usdhc.set_timeout(Timeout::Polls(5));
+    usdhc
+        .enable_dll(DllConfig {
+            override_delay: Some(5),
+            ..Default::default()
+        })
+        .unwrap();
+}
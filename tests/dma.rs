@@ -0,0 +1,429 @@
+//! Tests for DMA transfers.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, offset, pattern, register, setup};
+use imxrt_usdhc::{
+    sim::{self, MemoryCard, Simulator},
+    Adma1Action, Adma1Table, Adma2Table, AdmaState, DmaSelect, Status, TableError, TransportError,
+    VectoredData, VectoredError,
+};
+use sdio_host::{common_cmd, BlockingSdioTransport, TransportData};
+
+// HOST_CTRL_CAP fields.
+const ADMAS: u32 = 1 << 20;
+
+/// Memory for the DMA.
+///
+/// Buffers in `data` can start on an ADMA1 page.
+#[repr(C, align(4096))]
+struct Memory {
+    data: [u8; 0x6000],
+    adma1: Adma1Table<8>,
+    adma2: Adma2Table<8>,
+}
+
+/// Allocate DMA memory for the simulator.
+///
+/// The memory lives for the rest of the test program.
+fn dma_memory(sim: &Simulator<MemoryCard>) -> &'static mut Memory {
+    let memory = Box::leak(Box::new(Memory {
+        data: [0; 0x6000],
+        adma1: Adma1Table::new(),
+        adma2: Adma2Table::new(),
+    }));
+    // Safety: the memory is never freed.
+    unsafe {
+        sim.set_dma_memory(
+            (memory as *mut Memory).cast(),
+            core::mem::size_of::<Memory>(),
+        )
+    };
+    memory
+}
+
+/// Returns a word-aligned buffer that's outside of DMA memory.
+fn outside_dma_memory(words: usize) -> &'static mut [u8] {
+    let words = Box::leak(vec![0u32; words].into_boxed_slice());
+    // Safety: a u32 slice is a valid, aligned byte slice.
+    unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr().cast(), words.len() * 4) }
+}
+
+#[test]
+fn simple_dma() {
+    let (sim, mut usdhc) = setup();
+    let memory = dma_memory(&sim);
+    usdhc.set_dma_enable(Some(DmaSelect::Simple));
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let data = pattern(512, 5);
+    memory.data[..512].copy_from_slice(&data);
+    usdhc
+        .transfer(
+            &common_cmd::write_single_block(7),
+            &mut response,
+            TransportData::Write {
+                buffer: &memory.data[..512],
+            },
+        )
+        .unwrap();
+    assert_eq!(&sim.card().storage()[7 * 512..8 * 512], &data[..]);
+
+    usdhc
+        .transfer(
+            &common_cmd::read_single_block(7),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut memory.data[0x1000..0x1200],
+            },
+        )
+        .unwrap();
+    assert_eq!(&memory.data[0x1000..0x1200], &data[..]);
+}
+
+#[test]
+fn simple_dma_restarts_at_boundaries() {
+    let (sim, mut usdhc) = setup();
+    let memory = dma_memory(&sim);
+    usdhc.set_dma_enable(Some(DmaSelect::Simple));
+    init(&mut usdhc);
+
+    // Twenty blocks from 0x600 cross two 4 KiB boundaries.
+    let data = pattern(20 * 512, 7);
+    sim.card().storage_mut()[..data.len()].copy_from_slice(&data);
+    let mut response = [0; 4];
+    usdhc
+        .transfer(
+            &common_cmd::read_multiple_blocks(0),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut memory.data[0x600..0x600 + 20 * 512],
+            },
+        )
+        .unwrap();
+    assert_eq!(&memory.data[0x600..0x600 + 20 * 512], &data[..]);
+    assert!(!usdhc.status().intersects(Status::DINT | Status::TC));
+}
+
+#[test]
+fn unaligned_buffers_use_the_cpu() {
+    let (sim, mut usdhc) = setup();
+    // No DMA memory, so any DMA would fail.
+    usdhc.set_dma_enable(Some(DmaSelect::Simple));
+    init(&mut usdhc);
+
+    #[repr(C, align(4))]
+    struct Buffer([u8; 516]);
+    let mut buffer = Buffer([0; 516]);
+    let data = pattern(512, 9);
+    sim.card().storage_mut()[..512].copy_from_slice(&data);
+
+    let mut response = [0; 4];
+    usdhc
+        .transfer(
+            &common_cmd::read_single_block(0),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer.0[2..514],
+            },
+        )
+        .unwrap();
+    assert_eq!(&buffer.0[2..514], &data[..]);
+}
+
+#[test]
+fn dma_errors_are_uncategorized() {
+    let (_sim, mut usdhc) = setup();
+    // No DMA memory, so the DMA can't reach the buffer.
+    usdhc.set_dma_enable(Some(DmaSelect::Simple));
+    init(&mut usdhc);
+
+    let buffer = outside_dma_memory(128);
+    let mut response = [0; 4];
+    assert_eq!(
+        usdhc.transfer(
+            &common_cmd::read_single_block(0),
+            &mut response,
+            TransportData::Read { buffer },
+        ),
+        Err(TransportError::uncategorized())
+    );
+    assert!(usdhc.status().contains(Status::DMAE));
+}
+
+#[test]
+fn adma2() {
+    let (sim, mut usdhc) = setup();
+    let Memory { data, adma2, .. } = dma_memory(&sim);
+    usdhc.set_adma2_table(adma2);
+    usdhc.set_dma_enable(Some(DmaSelect::Adma2));
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let expected = pattern(3 * 512, 11);
+    data[0x100..0x100 + 3 * 512].copy_from_slice(&expected);
+    usdhc
+        .transfer(
+            &common_cmd::write_multiple_blocks(20),
+            &mut response,
+            TransportData::Write {
+                buffer: &data[0x100..0x100 + 3 * 512],
+            },
+        )
+        .unwrap();
+    assert_eq!(&sim.card().storage()[20 * 512..23 * 512], &expected[..]);
+
+    usdhc
+        .transfer(
+            &common_cmd::read_multiple_blocks(20),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut data[0x2000..0x2000 + 3 * 512],
+            },
+        )
+        .unwrap();
+    assert_eq!(&data[0x2000..0x2000 + 3 * 512], &expected[..]);
+}
+
+#[test]
+fn adma2_vectored() {
+    let (sim, mut usdhc) = setup();
+    let Memory { data, adma2, .. } = dma_memory(&sim);
+    usdhc.set_adma2_table(adma2);
+    usdhc.set_dma_enable(Some(DmaSelect::Adma2));
+    init(&mut usdhc);
+
+    let expected = pattern(1024, 13);
+    data[0x100..0x300].copy_from_slice(&expected[..512]);
+    data[0x1000..0x1200].copy_from_slice(&expected[512..]);
+    let (head, tail) = data.split_at_mut(0x1000);
+    let mut response = [0; 4];
+    usdhc
+        .transfer_vectored(
+            &common_cmd::write_multiple_blocks(30),
+            &mut response,
+            VectoredData::Write(&[&head[0x100..0x300], &tail[..0x200]]),
+        )
+        .unwrap();
+    assert_eq!(&sim.card().storage()[30 * 512..32 * 512], &expected[..]);
+
+    let (first, rest) = tail[0x1000..0x1400].split_at_mut(0x100);
+    let (second, third) = rest.split_at_mut(0x200);
+    usdhc
+        .transfer_vectored(
+            &common_cmd::read_multiple_blocks(30),
+            &mut response,
+            VectoredData::Read(&mut [first, second, third]),
+        )
+        .unwrap();
+    assert_eq!(&tail[0x1000..0x1400], &expected[..]);
+}
+
+#[test]
+fn vectored_errors() {
+    let (sim, mut usdhc) = setup();
+    let Memory { data, adma2, .. } = dma_memory(&sim);
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let command = common_cmd::write_multiple_blocks(0);
+    assert_eq!(
+        usdhc.transfer_vectored(
+            &command,
+            &mut response,
+            VectoredData::Write(&[&data[..512]])
+        ),
+        Err(VectoredError::NoTable)
+    );
+    usdhc.set_dma_enable(Some(DmaSelect::Adma2));
+    assert_eq!(
+        usdhc.transfer_vectored(
+            &command,
+            &mut response,
+            VectoredData::Write(&[&data[..512]])
+        ),
+        Err(VectoredError::NoTable)
+    );
+
+    usdhc.set_adma2_table(adma2);
+    assert_eq!(
+        usdhc.transfer_vectored(&command, &mut response, VectoredData::Write(&[])),
+        Err(VectoredError::Table(TableError::Empty))
+    );
+    assert_eq!(
+        usdhc.transfer_vectored(
+            &command,
+            &mut response,
+            VectoredData::Write(&[&data[1..513]])
+        ),
+        Err(VectoredError::Table(TableError::Alignment))
+    );
+    let buffers: Vec<&[u8]> = data[..9 * 64].chunks(64).collect();
+    assert_eq!(
+        usdhc.transfer_vectored(&command, &mut response, VectoredData::Write(&buffers)),
+        Err(VectoredError::Table(TableError::TooManyDescriptors))
+    );
+
+    // Without ADMA, the host never starts the DMA.
+    sim.set_capabilities(sim::HOST_CTRL_CAP & !ADMAS);
+    assert_eq!(
+        usdhc.transfer_vectored(
+            &command,
+            &mut response,
+            VectoredData::Write(&[&data[..512]])
+        ),
+        Err(VectoredError::NotSupported)
+    );
+    assert_eq!(register(&sim, offset::ADMA_SYS_ADDR), 0);
+    assert_eq!(
+        TransportError::from(VectoredError::NotSupported),
+        TransportError::NotSupported
+    );
+}
+
+#[test]
+fn adma2_describe() {
+    let buffer = outside_dma_memory(0x4004);
+    let mut table: Adma2Table<4> = Adma2Table::new();
+    assert!(!table.descriptors()[0].is_valid());
+
+    // The first buffer needs two descriptors.
+    let used = table
+        .describe([&buffer[..0x10000], &buffer[0x10000..0x10010]])
+        .unwrap();
+    assert_eq!(used, 3);
+    let descriptors = table.descriptors();
+    assert_eq!(descriptors[0].length(), 0xFFFC);
+    assert_eq!(descriptors[0].address(), buffer.as_ptr() as u32);
+    assert_eq!(descriptors[1].length(), 4);
+    assert_eq!(descriptors[1].address(), buffer.as_ptr() as u32 + 0xFFFC);
+    assert_eq!(descriptors[2].length(), 0x10);
+    assert!(descriptors[..3]
+        .iter()
+        .all(|descriptor| descriptor.is_valid()));
+    assert!(descriptors[2].is_end() && !descriptors[1].is_end());
+
+    assert_eq!(table.describe([&buffer[2..6]]), Err(TableError::Alignment));
+    assert_eq!(table.describe([&buffer[..0]]), Err(TableError::Empty));
+    assert_eq!(
+        table.describe([&buffer[..0x10000]; 3]),
+        Err(TableError::TooManyDescriptors)
+    );
+}
+
+#[test]
+fn adma2_errors() {
+    let (sim, mut usdhc) = setup();
+    let Memory { adma2, .. } = dma_memory(&sim);
+    usdhc.set_adma2_table(adma2);
+    usdhc.set_dma_enable(Some(DmaSelect::Adma2));
+    init(&mut usdhc);
+    assert_eq!(usdhc.adma_error(), None);
+
+    let buffer = outside_dma_memory(128);
+    let mut response = [0; 4];
+    assert_eq!(
+        usdhc.transfer(
+            &common_cmd::read_single_block(0),
+            &mut response,
+            TransportData::Read { buffer },
+        ),
+        Err(TransportError::uncategorized())
+    );
+    let error = usdhc.adma_error().unwrap();
+    assert_eq!(error.state, AdmaState::Transfer);
+    assert!(!error.length_mismatch && !error.descriptor_error);
+}
+
+#[test]
+fn adma1() {
+    let (sim, mut usdhc) = setup();
+    let Memory { data, adma1, .. } = dma_memory(&sim);
+    let table = adma1.descriptors().as_ptr() as u32;
+    usdhc.set_adma1_table(adma1);
+    usdhc.set_dma_enable(Some(DmaSelect::Adma1));
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let expected = pattern(3 * 512, 15);
+    data[0x1000..0x1000 + 3 * 512].copy_from_slice(&expected);
+    usdhc
+        .transfer(
+            &common_cmd::write_multiple_blocks(40),
+            &mut response,
+            TransportData::Write {
+                buffer: &data[0x1000..0x1000 + 3 * 512],
+            },
+        )
+        .unwrap();
+    assert_eq!(&sim.card().storage()[40 * 512..43 * 512], &expected[..]);
+    // The DMA fetched a set descriptor and a transfer descriptor.
+    assert_eq!(register(&sim, offset::ADMA_SYS_ADDR), table + 8);
+
+    usdhc
+        .transfer(
+            &common_cmd::read_multiple_blocks(40),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut data[0x3000..0x3000 + 3 * 512],
+            },
+        )
+        .unwrap();
+    assert_eq!(&data[0x3000..0x3000 + 3 * 512], &expected[..]);
+}
+
+#[test]
+fn adma1_vectored() {
+    let (sim, mut usdhc) = setup();
+    let Memory { data, adma1, .. } = dma_memory(&sim);
+    usdhc.set_adma1_table(adma1);
+    usdhc.set_dma_enable(Some(DmaSelect::Adma1));
+    init(&mut usdhc);
+
+    let expected = pattern(1024, 17);
+    sim.card().storage_mut()[50 * 512..52 * 512].copy_from_slice(&expected);
+    let (first, rest) = data.split_at_mut(0x2000);
+    let mut response = [0; 4];
+    usdhc
+        .transfer_vectored(
+            &common_cmd::read_multiple_blocks(50),
+            &mut response,
+            VectoredData::Read(&mut [&mut first[..0x200], &mut rest[..0x200]]),
+        )
+        .unwrap();
+    assert_eq!(&data[..0x200], &expected[..512]);
+    assert_eq!(&data[0x2000..0x2200], &expected[512..]);
+}
+
+#[test]
+fn adma1_describe() {
+    #[repr(C, align(4096))]
+    struct Pages([u8; 0x12000]);
+    let pages = Box::new(Pages([0; 0x12000]));
+    let pages = &pages.0;
+
+    let mut table: Adma1Table<8> = Adma1Table::new();
+    let used = table
+        .describe([&pages[..0x11000], &pages[0x11000..0x11200]])
+        .unwrap();
+    assert_eq!(used, 6);
+    let descriptors = table.descriptors();
+    assert_eq!(descriptors[0].action(), Adma1Action::Set);
+    assert_eq!(descriptors[0].value(), 0xF000);
+    assert_eq!(descriptors[1].action(), Adma1Action::Transfer);
+    assert_eq!(descriptors[1].value(), pages.as_ptr() as u32);
+    assert_eq!(descriptors[2].value(), 0x2000);
+    assert_eq!(descriptors[5].value(), pages.as_ptr() as u32 + 0x11000);
+    assert!(descriptors[5].is_end() && !descriptors[4].is_end());
+    assert!(!descriptors[6].is_valid());
+
+    assert_eq!(table.describe([&pages[4..8]]), Err(TableError::Alignment));
+    let mut small: Adma1Table<3> = Adma1Table::new();
+    assert_eq!(
+        small.describe([&pages[..0x11000]]),
+        Err(TableError::TooManyDescriptors)
+    );
+}
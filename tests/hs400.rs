@@ -0,0 +1,135 @@
+//! Tests for eMMC HS400.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, offset, register, setup, ROOT_CLOCK_HZ};
+use imxrt_usdhc::{
+    sim::{Card, DataError, MemoryCard, Response, Simulator},
+    BusMode, DataTransferWidth, Hs400Error, StandardTuning, TransportError, Usdhc,
+};
+
+// MIX_CTRL fields.
+const DDR_EN: u32 = 1 << 3;
+const SMP_CLK_SEL: u32 = 1 << 23;
+const HS400_MODE: u32 = 1 << 26;
+
+/// SWITCH to high speed timing.
+const SWITCH_HS_TIMING_HS: u32 = 0b11 << 24 | 185 << 16 | 1 << 8;
+
+/// An eMMC device that leaves HS200 during the bring-up.
+///
+/// Once the device switches to high speed timing, it rejects tuning commands.
+struct Device {
+    card: MemoryCard,
+    /// Requests re-tuning when the device leaves HS200.
+    sim: *const Simulator<Device>,
+    /// Reject the switch to high speed timing.
+    reject_switch: bool,
+    high_speed: bool,
+}
+
+impl Card for Device {
+    fn command(&mut self, index: u8, argument: u32) -> Option<Response> {
+        match (index, argument) {
+            (6, SWITCH_HS_TIMING_HS) if self.reject_switch => return None,
+            (6, SWITCH_HS_TIMING_HS) => {
+                self.high_speed = true;
+                // Safety: the simulator owns this card, so it outlives the card.
+                unsafe { (*self.sim).request_retuning() };
+            }
+            (21, _) if self.high_speed => return None,
+            _ => {}
+        }
+        self.card.command(index, argument)
+    }
+    fn read_block(&mut self, block: &mut [u8]) -> Result<(), DataError> {
+        self.card.read_block(block)
+    }
+    fn write_block(&mut self, block: &[u8]) -> Result<(), DataError> {
+        self.card.write_block(block)
+    }
+}
+
+/// Create a simulator for the device, and a driver ready for HS400.
+fn device(reject_switch: bool) -> (Box<Simulator<Device>>, Usdhc) {
+    let sim = Simulator::new(Device {
+        card: MemoryCard::new(1024),
+        sim: core::ptr::null(),
+        reject_switch,
+        high_speed: false,
+    });
+    sim.card().sim = &*sim;
+    // Safety: the simulator outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), 400_000_000) };
+    init(&mut usdhc);
+    usdhc.set_data_transfer_width(DataTransferWidth::Bit8);
+    (sim, usdhc)
+}
+
+#[test]
+fn hs400() {
+    let sim = Simulator::new(MemoryCard::new(1024));
+    // Safety: the simulator outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), 400_000_000) };
+    // The memory card answers CMD6 like an SD card, which is enough here.
+    init(&mut usdhc);
+
+    assert_eq!(
+        usdhc.enable_hs400(StandardTuning::default(), 7),
+        Err(Hs400Error::BusWidth)
+    );
+    usdhc.set_data_transfer_width(DataTransferWidth::Bit8);
+    assert_eq!(
+        usdhc.enable_hs400(StandardTuning::default(), 7),
+        Ok(200_000_000)
+    );
+    assert_eq!(usdhc.bus_mode(), BusMode::MmcHs400);
+    let flags = HS400_MODE | DDR_EN | SMP_CLK_SEL;
+    assert_eq!(register(&sim, offset::MIX_CTRL) & flags, flags);
+    let strobe = usdhc.strobe_dll_status();
+    assert!(strobe.slave_lock);
+    assert_eq!(strobe.slave_select, 7);
+
+    // The driver can't re-tune in HS400.
+    sim.request_retuning();
+    assert!(!usdhc.needs_retuning());
+
+    usdhc.set_bus_mode(BusMode::MmcHighSpeed).unwrap();
+    assert_eq!(register(&sim, offset::MIX_CTRL) & HS400_MODE, 0);
+}
+
+#[test]
+fn slow_root_clock() {
+    let (_sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_data_transfer_width(DataTransferWidth::Bit8);
+    // The fastest clock is the root clock, divided by two.
+    assert_eq!(
+        usdhc.enable_hs400(StandardTuning::default(), 7),
+        Ok(ROOT_CLOCK_HZ / 2)
+    );
+}
+
+#[test]
+fn no_retuning_after_hs200() {
+    let (sim, mut usdhc) = device(false);
+    assert_eq!(
+        usdhc.enable_hs400(StandardTuning::default(), 7),
+        Ok(200_000_000)
+    );
+    assert!(sim.card().high_speed);
+    assert!(!usdhc.needs_retuning());
+}
+
+#[test]
+fn failed_switch_disarms_retuning() {
+    let (sim, mut usdhc) = device(true);
+    assert_eq!(
+        usdhc.enable_hs400(StandardTuning::default(), 7),
+        Err(Hs400Error::Switch(TransportError::CommandTimeout))
+    );
+    sim.request_retuning();
+    assert!(!usdhc.needs_retuning());
+}
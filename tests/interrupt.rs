@@ -0,0 +1,202 @@
+//! Tests for the interrupt-driven transfers.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, pattern, setup, RCA, ROOT_CLOCK_HZ};
+use imxrt_usdhc::{
+    sim::{Card, DataError, MemoryCard, Response, Simulator},
+    DmaSelect, Status, TransferState, TransportError, Usdhc,
+};
+use sdio_host::{
+    common_cmd::{self, cmd, R1},
+    TransportData,
+};
+
+/// Call the interrupt handler until the simulator stops interrupting.
+fn run_interrupts<C: Card>(sim: &Simulator<C>, usdhc: &mut Usdhc) -> TransferState {
+    for _ in 0..100 {
+        if !sim.interrupt_pending() {
+            return usdhc.transfer_state();
+        }
+        usdhc.on_interrupt();
+    }
+    panic!("The simulator never stopped interrupting");
+}
+
+#[test]
+fn cpu_transfers() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_status_interrupt(Status::CINS);
+
+    let data = pattern(512, 37);
+    // Safety: the buffer outlives the transfer.
+    unsafe {
+        usdhc
+            .start_transfer(
+                &common_cmd::write_single_block(9),
+                TransportData::Write { buffer: &data },
+            )
+            .unwrap();
+    }
+    assert_eq!(usdhc.transfer_state(), TransferState::Command);
+    // Safety: the transfer never starts.
+    assert!(unsafe { usdhc.start_transfer(&common_cmd::idle(), TransportData::None) }.is_err());
+
+    assert_eq!(run_interrupts(&sim, &mut usdhc), TransferState::Complete);
+    // The driver leaves other interrupt signals alone.
+    assert_eq!(usdhc.status_interrupt(), Status::CINS);
+    assert!(usdhc.transfer_result().unwrap().is_ok());
+    assert_eq!(usdhc.transfer_state(), TransferState::Idle);
+    assert_eq!(&sim.card().storage()[9 * 512..10 * 512], &data[..]);
+
+    let mut buffer = [0; 512];
+    // Safety: the buffer outlives the transfer.
+    unsafe {
+        usdhc
+            .start_transfer(
+                &common_cmd::read_single_block(9),
+                TransportData::Read {
+                    buffer: &mut buffer,
+                },
+            )
+            .unwrap();
+    }
+    assert!(usdhc.transfer_result().is_none());
+    assert_eq!(run_interrupts(&sim, &mut usdhc), TransferState::Complete);
+    assert!(usdhc.transfer_result().unwrap().is_ok());
+    assert_eq!(&buffer[..], &data[..]);
+}
+
+#[test]
+fn responses() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    // Safety: there's no buffer.
+    unsafe {
+        usdhc
+            .start_transfer(&cmd::<R1>(13, RCA), TransportData::None)
+            .unwrap();
+    }
+    assert_eq!(usdhc.on_interrupt(), TransferState::Complete);
+    let response = usdhc.transfer_result().unwrap().unwrap();
+    // The card is in the transfer state.
+    assert_eq!(response[0] >> 9 & 0xF, 4);
+
+    sim.set_card_inserted(false);
+    // Safety: there's no buffer.
+    unsafe {
+        usdhc
+            .start_transfer(&cmd::<R1>(13, RCA), TransportData::None)
+            .unwrap();
+    }
+    assert_eq!(
+        usdhc.on_interrupt(),
+        TransferState::Error(TransportError::CommandTimeout)
+    );
+    assert!(usdhc.transfer_result().unwrap().is_err());
+}
+
+/// A card that fails the first block read with a CRC error.
+struct BadRead(MemoryCard, bool);
+
+impl Card for BadRead {
+    fn command(&mut self, index: u8, argument: u32) -> Option<Response> {
+        self.0.command(index, argument)
+    }
+    fn read_block(&mut self, block: &mut [u8]) -> Result<(), DataError> {
+        if core::mem::take(&mut self.1) {
+            return Err(DataError::Crc);
+        }
+        self.0.read_block(block)
+    }
+    fn write_block(&mut self, block: &[u8]) -> Result<(), DataError> {
+        self.0.write_block(block)
+    }
+}
+
+#[test]
+fn recovers_from_errors() {
+    let sim = Simulator::new(BadRead(MemoryCard::new(1024), true));
+    // Safety: the simulator outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), ROOT_CLOCK_HZ) };
+    init(&mut usdhc);
+
+    let mut buffer = [0; 512];
+    for expected in [
+        TransferState::Error(TransportError::Crc),
+        TransferState::Complete,
+    ] {
+        // Safety: the buffer outlives the transfer.
+        unsafe {
+            usdhc
+                .start_transfer(
+                    &common_cmd::read_single_block(0),
+                    TransportData::Read {
+                        buffer: &mut buffer,
+                    },
+                )
+                .unwrap();
+        }
+        assert_eq!(run_interrupts(&sim, &mut usdhc), expected);
+        usdhc.transfer_result().unwrap().ok();
+        assert_eq!(usdhc.status_interrupt(), Status::empty());
+    }
+}
+
+#[test]
+fn simple_dma_restarts_at_boundaries() {
+    #[repr(C, align(4096))]
+    struct Memory([u8; 0x3000]);
+
+    let (sim, mut usdhc) = setup();
+    let mut memory = Box::new(Memory([0; 0x3000]));
+    // Safety: the memory outlives the simulator's DMA.
+    unsafe { sim.set_dma_memory(memory.0.as_mut_ptr(), memory.0.len()) };
+    usdhc.set_dma_enable(Some(DmaSelect::Simple));
+    init(&mut usdhc);
+
+    // Eight blocks from 0x800 cross two 4 KiB boundaries.
+    let data = pattern(8 * 512, 41);
+    sim.card().storage_mut()[..data.len()].copy_from_slice(&data);
+    let buffer = &mut memory.0[0x800..0x800 + 8 * 512];
+    // Safety: the buffer outlives the transfer.
+    unsafe {
+        usdhc
+            .start_transfer(
+                &common_cmd::read_multiple_blocks(0),
+                TransportData::Read { buffer },
+            )
+            .unwrap();
+    }
+    assert_eq!(run_interrupts(&sim, &mut usdhc), TransferState::Complete);
+    usdhc.transfer_result().unwrap().unwrap();
+    assert_eq!(&memory.0[0x800..0x800 + 8 * 512], &data[..]);
+    assert!(!usdhc.status().intersects(Status::DINT | Status::TC));
+}
+
+#[test]
+fn abort() {
+    let (_sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_status_interrupt(Status::CINS);
+
+    let mut buffer = [0; 512];
+    // Safety: the abort ends the transfer before the buffer goes away.
+    unsafe {
+        usdhc
+            .start_transfer(
+                &common_cmd::read_single_block(9),
+                TransportData::Read {
+                    buffer: &mut buffer,
+                },
+            )
+            .unwrap();
+    }
+    usdhc.abort_transfer().unwrap();
+    assert_eq!(usdhc.transfer_state(), TransferState::Idle);
+    assert_eq!(usdhc.status_interrupt(), Status::CINS);
+}
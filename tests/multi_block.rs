@@ -0,0 +1,174 @@
+//! Tests for multi-block transfers.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, offset, pattern, register, setup, RCA, ROOT_CLOCK_HZ};
+use imxrt_usdhc::{
+    sim::{Card, DataError, MemoryCard, Response, Simulator},
+    AutoCmd12Error, DmaSelect, MultiBlockMode, SetBlockCount, TransportError, Usdhc,
+};
+use sdio_host::{
+    common_cmd::{self, cmd, R1},
+    BlockingSdioTransport, TransportData,
+};
+
+// MIX_CTRL fields.
+const BCEN: u32 = 1 << 1;
+const AC12EN: u32 = 1 << 2;
+const MSBSEL: u32 = 1 << 5;
+const AC23EN: u32 = 1 << 7;
+
+// VEND_SPEC2 fields.
+const ACMD23_ARGU2_EN: u32 = 1 << 12;
+
+/// A card that never responds to CMD12.
+struct NoStop(MemoryCard);
+
+impl Card for NoStop {
+    fn command(&mut self, index: u8, argument: u32) -> Option<Response> {
+        match index {
+            12 => None,
+            _ => self.0.command(index, argument),
+        }
+    }
+    fn read_block(&mut self, block: &mut [u8]) -> Result<(), DataError> {
+        self.0.read_block(block)
+    }
+    fn write_block(&mut self, block: &[u8]) -> Result<(), DataError> {
+        self.0.write_block(block)
+    }
+}
+
+/// Returns the card's current state.
+fn card_state(usdhc: &mut Usdhc) -> u32 {
+    let mut response = [0; 4];
+    usdhc
+        .transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        .unwrap();
+    response[0] >> 9 & 0xF
+}
+
+#[test]
+fn auto_cmd12() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let data = pattern(4 * 512, 19);
+    usdhc
+        .transfer(
+            &common_cmd::write_multiple_blocks(60),
+            &mut response,
+            TransportData::Write { buffer: &data },
+        )
+        .unwrap();
+    assert_eq!(&sim.card().storage()[60 * 512..64 * 512], &data[..]);
+    // The peripheral counted the blocks, and stopped the transfer.
+    let flags = MSBSEL | AC12EN | BCEN;
+    assert_eq!(register(&sim, offset::MIX_CTRL) & flags, flags);
+    assert_eq!(card_state(&mut usdhc), 4);
+
+    let mut buffer = [0; 4 * 512];
+    usdhc
+        .transfer(
+            &common_cmd::read_multiple_blocks(60),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        )
+        .unwrap();
+    assert_eq!(&buffer[..], &data[..]);
+    assert_eq!(card_state(&mut usdhc), 4);
+}
+
+#[test]
+fn partial_blocks_are_not_supported() {
+    let (_sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let mut buffer = [0; 600];
+    assert_eq!(
+        usdhc.transfer(
+            &common_cmd::read_multiple_blocks(0),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        ),
+        Err(TransportError::NotSupported)
+    );
+}
+
+#[test]
+fn auto_cmd12_errors() {
+    let sim = Simulator::new(NoStop(MemoryCard::new(1024)));
+    // Safety: the simulator is boxed, and it outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), ROOT_CLOCK_HZ) };
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let mut buffer = [0; 2 * 512];
+    assert_eq!(
+        usdhc.transfer(
+            &common_cmd::read_multiple_blocks(0),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        ),
+        Err(TransportError::CommandTimeout)
+    );
+    assert!(usdhc.auto_cmd12_error().contains(AutoCmd12Error::AC12TOE));
+}
+
+#[test]
+fn auto_cmd23() {
+    let (sim, mut usdhc) = setup();
+    usdhc.set_multi_block_mode(MultiBlockMode::AutoCmd23(SetBlockCount::empty()));
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let data = pattern(3 * 512, 23);
+    usdhc
+        .transfer(
+            &common_cmd::write_multiple_blocks(70),
+            &mut response,
+            TransportData::Write { buffer: &data },
+        )
+        .unwrap();
+    assert_eq!(&sim.card().storage()[70 * 512..73 * 512], &data[..]);
+    let mix_ctrl = register(&sim, offset::MIX_CTRL);
+    assert_eq!(mix_ctrl & (AC23EN | AC12EN), AC23EN);
+    assert_eq!(register(&sim, offset::VEND_SPEC2) & ACMD23_ARGU2_EN, 0);
+    // The card ended the transfer after the counted blocks.
+    assert_eq!(card_state(&mut usdhc), 4);
+}
+
+#[test]
+fn auto_cmd23_flags_avoid_simple_dma() {
+    // No DMA memory, so any simple DMA would fail.
+    let (sim, mut usdhc) = setup();
+    usdhc.set_dma_enable(Some(DmaSelect::Simple));
+    let flags = SetBlockCount::RELIABLE_WRITE;
+    usdhc.set_multi_block_mode(MultiBlockMode::AutoCmd23(flags));
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let data = pattern(2 * 512, 29);
+    usdhc
+        .transfer(
+            &common_cmd::write_multiple_blocks(80),
+            &mut response,
+            TransportData::Write { buffer: &data },
+        )
+        .unwrap();
+    assert_eq!(&sim.card().storage()[80 * 512..82 * 512], &data[..]);
+    // The simple DMA address register holds the CMD23 argument.
+    assert_eq!(register(&sim, offset::DS_ADDR), flags.bits() | 2);
+    assert_ne!(register(&sim, offset::VEND_SPEC2) & ACMD23_ARGU2_EN, 0);
+    assert_eq!(card_state(&mut usdhc), 4);
+}
@@ -0,0 +1,173 @@
+//! Tests for the simulator, using the blocking transport.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, offset, pattern, register, setup, RCA};
+use imxrt_usdhc::{
+    sim::{MemoryCard, Simulator},
+    PresentState, Status, Timeout, TransportError,
+};
+use sdio_host::{
+    common_cmd::{self, cmd, Rz, R1, R2},
+    BlockingSdioTransport, TransportData,
+};
+
+#[test]
+fn identify_card() {
+    let (_sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    usdhc
+        .transfer(&cmd::<R2>(10, RCA), &mut response, TransportData::None)
+        .unwrap();
+    // The peripheral drops the CRC byte. The transport shifts the response
+    // back into place, and zero-fills the low byte.
+    assert_eq!(response[3], MemoryCard::CID[3]);
+    assert_eq!(response[0] >> 8, MemoryCard::CID[0] >> 8);
+    assert_eq!(response[0] & 0xFF, 0);
+
+    usdhc
+        .transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        .unwrap();
+    // The card is in the transfer state.
+    assert_eq!(response[0] >> 9 & 0xF, 4);
+}
+
+#[test]
+fn read_and_write_blocks() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let data = pattern(512, 3);
+    usdhc
+        .transfer(
+            &common_cmd::write_single_block(5),
+            &mut response,
+            TransportData::Write { buffer: &data },
+        )
+        .unwrap();
+    assert_eq!(&sim.card().storage()[5 * 512..6 * 512], &data[..]);
+
+    let mut buffer = [0; 512];
+    usdhc
+        .transfer(
+            &common_cmd::read_single_block(5),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        )
+        .unwrap();
+    assert_eq!(&buffer[..], &data[..]);
+}
+
+#[test]
+fn read_smaller_than_a_block() {
+    let (_sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    let mut response = [0; 4];
+    let mut scr = [0; 8];
+    usdhc
+        .transfer(&cmd::<R1>(55, RCA), &mut response, TransportData::None)
+        .unwrap();
+    usdhc
+        .transfer(
+            &cmd::<R1>(51, 0),
+            &mut response,
+            TransportData::Read { buffer: &mut scr },
+        )
+        .unwrap();
+    assert_eq!(scr[..2], [0x02, 0x35]);
+}
+
+#[test]
+fn removed_card_times_out() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    assert!(usdhc.present_state().contains(PresentState::CINST));
+
+    sim.set_card_inserted(false);
+    assert!(!usdhc.present_state().contains(PresentState::CINST));
+    assert!(usdhc.status().contains(Status::CRM));
+
+    let mut response = [0; 4];
+    assert_eq!(
+        usdhc.transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None),
+        Err(TransportError::CommandTimeout)
+    );
+}
+
+#[test]
+fn status_flags_clear_on_write() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    sim.set_card_inserted(false);
+    sim.set_card_inserted(true);
+    assert!(usdhc.status().contains(Status::CINS | Status::CRM));
+    usdhc.clear_status(Status::CRM);
+    assert_eq!(usdhc.status() & (Status::CINS | Status::CRM), Status::CINS);
+
+    // Disabled flags never appear.
+    usdhc.set_status_enable(Status::empty());
+    sim.set_card_inserted(false);
+    assert!(!usdhc.status().contains(Status::CRM));
+}
+
+#[test]
+fn software_reset() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    sim.set_card_inserted(false);
+    assert!(usdhc.status().contains(Status::CRM));
+
+    usdhc.software_reset().unwrap();
+    assert!(usdhc.status().is_empty());
+    assert!(!usdhc
+        .present_state()
+        .intersects(PresentState::CIHB | PresentState::CDIHB));
+}
+
+/// Returns the response type of the last command.
+fn last_response_type(sim: &Simulator<MemoryCard>) -> u32 {
+    register(sim, offset::CMD_XFR_TYP) >> 16 & 0b11
+}
+
+#[test]
+fn busy_responses() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    // Selecting the card signals busy.
+    assert_eq!(last_response_type(&sim), 3);
+
+    let mut response = [0; 4];
+    usdhc
+        .transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        .unwrap();
+    assert_eq!(last_response_type(&sim), 2);
+}
+
+#[test]
+fn deselect_card() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    // The card never signals busy, so there's nothing to wait for.
+    usdhc.set_timeout(Timeout::Polls(100));
+
+    let mut response = [0; 4];
+    usdhc
+        .transfer(&cmd::<Rz>(7, 0), &mut response, TransportData::None)
+        .unwrap();
+    assert_eq!(last_response_type(&sim), 0);
+
+    usdhc
+        .transfer(&cmd::<R1>(13, RCA), &mut response, TransportData::None)
+        .unwrap();
+    // The card is in the standby state.
+    assert_eq!(response[0] >> 9 & 0xF, 3);
+}
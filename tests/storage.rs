@@ -0,0 +1,104 @@
+//! Tests for the block storage.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, pattern, ROOT_CLOCK_HZ};
+use imxrt_usdhc::{
+    sim::{MemoryCard, Simulator},
+    BlockStorage, TransportError, Usdhc,
+};
+use sdio_host::sd::{CSD, SD};
+
+#[test]
+fn block_storage() {
+    let sim = Simulator::new(MemoryCard::new(2048));
+    // Safety: the simulator outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), ROOT_CLOCK_HZ) };
+    init(&mut usdhc);
+    let csd = CSD::<SD>::from(sim.card().csd());
+    let mut storage = BlockStorage::new(usdhc, csd);
+    assert_eq!(storage.block_count(), 2048);
+
+    let data = pattern(3 * 512, 3);
+    storage.write_blocks(10, &data).unwrap();
+    assert_eq!(&sim.card().storage()[10 * 512..13 * 512], &data[..]);
+
+    let mut buffer = vec![0; 3 * 512];
+    storage.read_blocks(10, &mut buffer).unwrap();
+    assert_eq!(buffer, data);
+    buffer.fill(0);
+    storage.read_blocks(11, &mut buffer[..512]).unwrap();
+    assert_eq!(&buffer[..512], &data[512..1024]);
+
+    // Beyond the capacity, or not a whole block.
+    assert_eq!(
+        storage.read_blocks(2047, &mut buffer[..1024]),
+        Err(TransportError::NotSupported)
+    );
+    assert_eq!(
+        storage.read_blocks(0, &mut buffer[..100]),
+        Err(TransportError::NotSupported)
+    );
+
+    let _usdhc = storage.release();
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+#[test]
+fn block_device() {
+    use embedded_sdmmc::{Block, BlockDevice, BlockIdx};
+
+    let sim = Simulator::new(MemoryCard::new(2048));
+    // Safety: the simulator outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), ROOT_CLOCK_HZ) };
+    init(&mut usdhc);
+    let storage = BlockStorage::new(usdhc, CSD::<SD>::from(sim.card().csd()));
+    assert_eq!(storage.num_blocks().unwrap().0, 2048);
+
+    let data = pattern(2 * 512, 5);
+    sim.card().storage_mut()[11 * 512..13 * 512].copy_from_slice(&data);
+    let mut blocks = [Block::new(), Block::new()];
+    storage.read(&mut blocks, BlockIdx(11), "test").unwrap();
+    assert_eq!(&blocks[0].contents[..], &data[..512]);
+    assert_eq!(&blocks[1].contents[..], &data[512..]);
+
+    blocks[0].contents = [0xAB; 512];
+    storage.write(&blocks[..1], BlockIdx(100)).unwrap();
+    assert!(sim.card().storage()[100 * 512..101 * 512]
+        .iter()
+        .all(|&byte| byte == 0xAB));
+}
+
+#[cfg(feature = "embedded-storage")]
+#[test]
+fn unaligned_storage() {
+    use embedded_storage::{ReadStorage, Storage};
+
+    let sim = Simulator::new(MemoryCard::new(2048));
+    // Safety: the simulator outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), ROOT_CLOCK_HZ) };
+    init(&mut usdhc);
+    let mut storage = BlockStorage::new(usdhc, CSD::<SD>::from(sim.card().csd()));
+    assert_eq!(storage.capacity(), 2048 * 512);
+
+    let data = pattern(3 * 512, 9);
+    sim.card().storage_mut()[10 * 512..13 * 512].copy_from_slice(&data);
+    let mut buffer = [0; 1400];
+    ReadStorage::read(&mut storage, 10 * 512 + 37, &mut buffer).unwrap();
+    assert_eq!(&buffer[..], &data[37..1437]);
+
+    // The write preserves the rest of the first and last blocks.
+    let data = pattern(2000, 13);
+    Storage::write(&mut storage, 200 * 512 + 100, &data).unwrap();
+    let card = sim.card();
+    let storage = card.storage();
+    assert_eq!(&storage[200 * 512 + 100..200 * 512 + 2100], &data[..]);
+    assert!(storage[200 * 512..200 * 512 + 100]
+        .iter()
+        .all(|&byte| byte == 0));
+    assert!(storage[200 * 512 + 2100..205 * 512]
+        .iter()
+        .all(|&byte| byte == 0));
+}
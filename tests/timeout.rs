@@ -0,0 +1,91 @@
+//! Tests for bounded waits.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use common::{init, setup, ROOT_CLOCK_HZ};
+use imxrt_usdhc::{PresentState, Status, Timeout, TimeoutError, TransportError, Usdhc};
+use sdio_host::{common_cmd, BlockingSdioTransport, TransportData};
+
+#[test]
+fn waits_finish_within_the_timeout() {
+    // The tick count wraps around during the test.
+    static NOW: AtomicU32 = AtomicU32::new(u32::MAX - 2);
+
+    let (_sim, mut usdhc) = setup();
+    usdhc.set_timeout(Timeout::Polls(10));
+    init(&mut usdhc);
+
+    usdhc.set_timeout(Timeout::Ticks {
+        now: || NOW.fetch_add(1, Ordering::Relaxed),
+        ticks: 100,
+    });
+    let mut response = [0; 4];
+    let mut buffer = [0; 512];
+    usdhc
+        .transfer(
+            &common_cmd::read_single_block(5),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        )
+        .unwrap();
+    usdhc.command_reset().unwrap();
+    usdhc.data_reset().unwrap();
+    usdhc.software_reset().unwrap();
+}
+
+#[test]
+fn busy_data_lines_time_out() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+
+    // Another driver starts a read, and never moves the data.
+    // Safety: the simulator outlives the driver.
+    let mut other = unsafe { Usdhc::new(sim.as_ptr(), ROOT_CLOCK_HZ) };
+    let mut stuck = [0; 512];
+    // Safety: the buffer outlives the driver, and nothing else accesses it.
+    unsafe {
+        other
+            .start_transfer(
+                &common_cmd::read_single_block(0),
+                TransportData::Read { buffer: &mut stuck },
+            )
+            .unwrap();
+    }
+    assert!(usdhc.present_state().contains(PresentState::CDIHB));
+
+    usdhc.set_timeout(Timeout::Polls(10));
+    let mut response = [0; 4];
+    let mut buffer = [0; 512];
+    assert_eq!(
+        usdhc.transfer(
+            &common_cmd::read_single_block(1),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        ),
+        Err(TransportError::DataTimeout)
+    );
+}
+
+#[test]
+fn timeout_errors() {
+    assert_eq!(
+        TransportError::from(TimeoutError::Inhibit(PresentState::CIHB)),
+        TransportError::CommandTimeout
+    );
+    assert_eq!(
+        TransportError::from(TimeoutError::Status(Status::CC)),
+        TransportError::CommandTimeout
+    );
+    assert_eq!(
+        TransportError::from(TimeoutError::Status(Status::TC)),
+        TransportError::DataTimeout
+    );
+}
@@ -0,0 +1,124 @@
+//! Tests for the card clock and data timeout.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{offset, register, setup};
+use imxrt_usdhc::{DDRPrescaler, DataRate, DataTimeout, SDRPrescaler, Timing};
+
+// SYS_CTRL fields.
+const SDCLKFS_OFFSET: u32 = 8;
+const SDCLKFS_MASK: u32 = 0xFF;
+const DTOCV_OFFSET: u32 = 16;
+const DTOCV_MASK: u32 = 0xF;
+
+// MIX_CTRL fields.
+const DDR_EN: u32 = 1 << 3;
+
+// MMC_BOOT fields.
+const DISABLE_TIME_OUT: u32 = 1 << 8;
+
+#[test]
+fn data_timeout() {
+    let (sim, mut usdhc) = setup();
+    usdhc.software_reset().unwrap();
+
+    let timing = |data_timeout| Timing {
+        divisor: 1,
+        data_rate: DataRate::SingleDataRate(SDRPrescaler::Divide1),
+        data_timeout,
+    };
+
+    usdhc.set_timing(timing(DataTimeout::Disabled)).unwrap();
+    assert_ne!(register(&sim, offset::MMC_BOOT) & DISABLE_TIME_OUT, 0);
+
+    // 250ms at 25 MHz is about 2^23 cycles.
+    let data_timeout = DataTimeout::from_micros(25_000_000, 250_000);
+    assert_eq!(data_timeout, DataTimeout::Cycles(23));
+    usdhc.set_timing(timing(data_timeout)).unwrap();
+    assert_eq!(register(&sim, offset::MMC_BOOT) & DISABLE_TIME_OUT, 0);
+    let dtocv = register(&sim, offset::SYS_CTRL) >> DTOCV_OFFSET & DTOCV_MASK;
+    assert_eq!(dtocv, 23 - 14);
+
+    // Out of range cycles are clamped.
+    usdhc.set_timing(timing(DataTimeout::Cycles(40))).unwrap();
+    let dtocv = register(&sim, offset::SYS_CTRL) >> DTOCV_OFFSET & DTOCV_MASK;
+    assert_eq!(dtocv, 29 - 14);
+}
+
+#[test]
+fn data_timeout_from_micros() {
+    assert_eq!(DataTimeout::from_micros(400_000, 1), DataTimeout::MIN);
+    assert_eq!(
+        DataTimeout::from_micros(400_000_000, 10_000_000),
+        DataTimeout::MAX
+    );
+    assert_eq!(DataTimeout::default(), DataTimeout::MIN);
+}
+
+#[test]
+fn prescalers() {
+    let sdr: Vec<u32> = SDRPrescaler::ALL.iter().map(|p| p.divide()).collect();
+    assert_eq!(sdr, [1, 2, 4, 8, 16, 32, 64, 128, 256]);
+    let ddr: Vec<u32> = DDRPrescaler::ALL.iter().map(|p| p.divide()).collect();
+    assert_eq!(ddr, [2, 4, 8, 16, 32, 64, 128, 256, 512]);
+
+    let (sim, mut usdhc) = setup();
+    usdhc.software_reset().unwrap();
+    let sdclkfs = || register(&sim, offset::SYS_CTRL) >> SDCLKFS_OFFSET & SDCLKFS_MASK;
+
+    usdhc
+        .set_timing(Timing {
+            divisor: 2,
+            data_rate: DataRate::SingleDataRate(SDRPrescaler::Divide256),
+            data_timeout: DataTimeout::MIN,
+        })
+        .unwrap();
+    assert_eq!(sdclkfs(), 0x80);
+    assert_eq!(register(&sim, offset::MIX_CTRL) & DDR_EN, 0);
+
+    usdhc
+        .set_timing(Timing {
+            divisor: 2,
+            data_rate: DataRate::DualDataRate(DDRPrescaler::Divide512),
+            data_timeout: DataTimeout::MIN,
+        })
+        .unwrap();
+    assert_eq!(sdclkfs(), 0x80);
+    assert_ne!(register(&sim, offset::MIX_CTRL) & DDR_EN, 0);
+
+    usdhc
+        .set_timing(Timing {
+            divisor: 2,
+            data_rate: DataRate::DualDataRate(DDRPrescaler::Divide2),
+            data_timeout: DataTimeout::MIN,
+        })
+        .unwrap();
+    assert_eq!(sdclkfs(), 0);
+}
+
+#[test]
+fn timing_calculator() {
+    for &(root, target, expected) in &[
+        (198_000_000, 400_000, 386_718),
+        (198_000_000, 25_000_000, 24_750_000),
+        (198_000_000, 50_000_000, 49_500_000),
+        (198_000_000, 100_000_000, 99_000_000),
+        (198_000_000, 208_000_000, 198_000_000),
+        (396_000_000, 208_000_000, 198_000_000),
+        (400_000_000, 400_000, 390_625),
+    ] {
+        let (timing, hz) = Timing::sdr(root, target).unwrap();
+        assert_eq!(hz, expected, "{root} Hz to {target} Hz: {timing:?}");
+        assert_eq!(hz, timing.card_clock_hz(root));
+
+        let (timing, hz) = Timing::ddr(root, target).unwrap();
+        assert!(hz <= target);
+        assert_eq!(hz, timing.card_clock_hz(root));
+    }
+
+    // Unreachable clocks.
+    assert!(Timing::sdr(600_000_000, 100_000).is_none());
+    assert!(Timing::ddr(600_000_000, 0).is_none());
+}
@@ -0,0 +1,153 @@
+//! Tests for sampling clock tuning.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, offset, register, setup};
+use imxrt_usdhc::{
+    sim::PASSING_TAPS, BusMode, DataTransferWidth, StandardTuning, TuningCommand, TuningError,
+    Usdhc,
+};
+use sdio_host::{common_cmd, BlockingSdioTransport, TransportData};
+
+// MIX_CTRL fields.
+const EXE_TUNE: u32 = 1 << 22;
+const SMP_CLK_SEL: u32 = 1 << 23;
+const AUTO_TUNE_EN: u32 = 1 << 24;
+
+/// Read a block, which only works with a good sampling clock.
+fn read_block(usdhc: &mut Usdhc) {
+    let mut response = [0; 4];
+    let mut buffer = [0; 512];
+    usdhc
+        .transfer(
+            &common_cmd::read_single_block(5),
+            &mut response,
+            TransportData::Read {
+                buffer: &mut buffer,
+            },
+        )
+        .unwrap();
+}
+
+#[test]
+fn standard_tuning() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_data_transfer_width(DataTransferWidth::Bit4);
+    usdhc.set_bus_mode(BusMode::SdSdr104).unwrap();
+
+    let tap = usdhc
+        .tune(TuningCommand::SdCmd19, StandardTuning::default())
+        .unwrap();
+    assert!(PASSING_TAPS.contains(&(tap as u32)), "{tap}");
+    assert_eq!(
+        register(&sim, offset::MIX_CTRL) & (EXE_TUNE | SMP_CLK_SEL),
+        SMP_CLK_SEL
+    );
+    assert_eq!(register(&sim, offset::BLK_ATT) & 0x1FFF, 64);
+    read_block(&mut usdhc);
+
+    let tuning = StandardTuning {
+        start_tap: 10,
+        step: 3,
+        counter: 40,
+        window: 4,
+        auto_tune: false,
+    };
+    let tap = usdhc.tune(TuningCommand::MmcCmd21, tuning).unwrap();
+    assert!(PASSING_TAPS.contains(&(tap as u32)), "{tap}");
+}
+
+#[test]
+fn emmc_tuning_block_doubles_on_8_bit_bus() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_data_transfer_width(DataTransferWidth::Bit8);
+    usdhc.set_bus_mode(BusMode::MmcHs200).unwrap();
+
+    usdhc
+        .tune(TuningCommand::MmcCmd21, StandardTuning::default())
+        .unwrap();
+    assert_eq!(register(&sim, offset::BLK_ATT) & 0x1FFF, 128);
+}
+
+#[test]
+fn failed_tuning_restores_the_fixed_clock() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_data_transfer_width(DataTransferWidth::Bit4);
+    usdhc.set_bus_mode(BusMode::SdSdr104).unwrap();
+
+    // The counter expires before reaching a passing tap.
+    let tuning = StandardTuning {
+        counter: 10,
+        ..Default::default()
+    };
+    assert_eq!(
+        usdhc.tune(TuningCommand::SdCmd19, tuning),
+        Err(TuningError::Failed)
+    );
+    assert_eq!(
+        register(&sim, offset::MIX_CTRL) & (EXE_TUNE | SMP_CLK_SEL),
+        0
+    );
+    read_block(&mut usdhc);
+}
+
+#[test]
+fn manual_tuning() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_data_transfer_width(DataTransferWidth::Bit4);
+    usdhc.set_bus_mode(BusMode::SdSdr104).unwrap();
+
+    let (tap, map) = usdhc.tune_manually(TuningCommand::SdCmd19).unwrap();
+    assert_eq!(map.widest_window(), Some(24..56));
+    // The center of the widest window.
+    assert_eq!(tap, 24 + 15);
+    assert!(map.passed(24) && !map.passed(23));
+    assert!(!map.passed(56) && !map.passed(200));
+    assert_eq!(
+        register(&sim, offset::MIX_CTRL) & (EXE_TUNE | SMP_CLK_SEL),
+        SMP_CLK_SEL
+    );
+    read_block(&mut usdhc);
+}
+
+#[test]
+fn retuning() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    usdhc.set_bus_mode(BusMode::SdSdr104).unwrap();
+
+    // Without tuning, there's nothing to repeat.
+    sim.request_retuning();
+    assert!(!usdhc.needs_retuning());
+    assert_eq!(usdhc.retune_if_needed(), Ok(None));
+
+    let tuning = StandardTuning {
+        auto_tune: true,
+        ..Default::default()
+    };
+    usdhc.tune(TuningCommand::SdCmd19, tuning).unwrap();
+    assert_ne!(register(&sim, offset::MIX_CTRL) & AUTO_TUNE_EN, 0);
+    assert!(!usdhc.needs_retuning());
+
+    // The transport re-tunes before the next command.
+    sim.request_retuning();
+    assert!(usdhc.needs_retuning());
+    read_block(&mut usdhc);
+    assert!(!usdhc.needs_retuning());
+
+    usdhc.tune_manually(TuningCommand::SdCmd19).unwrap();
+    sim.request_retuning();
+    assert!(usdhc.retune_if_needed().unwrap().is_some());
+    assert!(!usdhc.needs_retuning());
+
+    // Modes without tuning forget the last tuning.
+    usdhc.set_bus_mode(BusMode::SdHighSpeed).unwrap();
+    sim.request_retuning();
+    assert!(!usdhc.needs_retuning());
+}
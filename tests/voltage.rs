@@ -0,0 +1,74 @@
+//! Tests for the 1.8V signaling switch.
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{init, offset, register, setup, ROOT_CLOCK_HZ};
+use imxrt_usdhc::{
+    sim::{self, Card, DataError, MemoryCard, Response, Simulator},
+    TransportError, Usdhc, VoltageSwitchError,
+};
+use sdio_host::BlockingSdioTransport;
+
+// VEND_SPEC fields.
+const VSELECT: u32 = 1 << 1;
+
+// HOST_CTRL_CAP fields.
+const VS18: u32 = 1 << 26;
+
+/// A card that accepts CMD11, but never drives its lines low.
+struct Uhs(MemoryCard);
+
+impl Card for Uhs {
+    fn command(&mut self, index: u8, argument: u32) -> Option<Response> {
+        match index {
+            11 => Some(Response::R48(0x900)),
+            _ => self.0.command(index, argument),
+        }
+    }
+    fn read_block(&mut self, block: &mut [u8]) -> Result<(), DataError> {
+        self.0.read_block(block)
+    }
+    fn write_block(&mut self, block: &[u8]) -> Result<(), DataError> {
+        self.0.write_block(block)
+    }
+}
+
+#[test]
+fn card_rejects_the_switch() {
+    let (sim, mut usdhc) = setup();
+    init(&mut usdhc);
+    assert_eq!(
+        usdhc.switch_to_1v8(&mut |_| {}),
+        Err(VoltageSwitchError::Command(TransportError::CommandTimeout))
+    );
+    assert_eq!(register(&sim, offset::VEND_SPEC) & VSELECT, 0);
+}
+
+#[test]
+fn card_never_starts_the_switch() {
+    let sim = Simulator::new(Uhs(MemoryCard::new(1024)));
+    // Safety: the simulator outlives the driver.
+    let mut usdhc = unsafe { Usdhc::new(sim.as_ptr(), ROOT_CLOCK_HZ) };
+    usdhc.power_cycle(&mut |_| {}).unwrap();
+    assert_eq!(usdhc.data_line_levels(), 0xFF);
+
+    assert_eq!(
+        usdhc.switch_to_1v8(&mut |_| {}),
+        Err(VoltageSwitchError::NotStarted)
+    );
+    // The host still uses 3.3V signaling.
+    assert_eq!(register(&sim, offset::VEND_SPEC) & VSELECT, 0);
+}
+
+#[test]
+fn host_without_1v8() {
+    let (sim, mut usdhc) = setup();
+    sim.set_capabilities(sim::HOST_CTRL_CAP & !VS18);
+    init(&mut usdhc);
+    assert_eq!(
+        usdhc.switch_to_1v8(&mut |_| {}),
+        Err(VoltageSwitchError::NotSupported)
+    );
+}